@@ -1,4 +1,5 @@
 //pub mod arr;
 
 pub mod common;
+pub mod exchange;
 pub mod vec;
@@ -1,6 +1,5 @@
-use ndarray::{array, Array, Array2, Ix2};
-use num_complex::{Complex, Complex64, ComplexFloat};
-use std::f64::consts::PI;
+use ndarray::{array, Array, Ix2};
+use num_complex::{Complex, Complex64};
 
 fn eiphi(phi: f64) -> Complex64 {
     (Complex::i() * phi).exp()
@@ -34,6 +33,9 @@ pub(crate) fn gen_rotation_matrix(alpha: f64, phi: f64) -> Array<Complex64, Ix2>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ndarray::Array2;
+    use num_complex::ComplexFloat;
+    use std::f64::consts::PI;
 
     fn matrix_close(a: Array<Complex64, Ix2>, b: Array<Complex64, Ix2>, epsilon: f64) -> bool {
         ndarray::Zip::from(&a)
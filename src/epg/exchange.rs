@@ -0,0 +1,103 @@
+use nalgebra::DMatrix;
+use ndarray::{Array, Ix2};
+use num_complex::Complex64;
+
+use super::vec::EPGVecRepresentation;
+use crate::types::EPG;
+
+/// Coupled pools exchanging magnetization (EPG-X, Gloor et al. 2008). Each pool keeps its
+/// own phase graph, relaxation and exchange act on matching states across pools.
+pub(crate) struct EPGExchange {
+    pools: Vec<EPGVecRepresentation>,
+    m0: Vec<f64>,
+    r1: Vec<f64>,
+    r2: Vec<f64>,
+    /// `k[(i, j)]` is the rate from pool j into pool i in 1/s, columns sum to zero.
+    k: DMatrix<f64>,
+}
+
+impl EPGExchange {
+    /// `m0` holds the equilibrium magnetization of each pool.
+    pub(crate) fn new(
+        n_states: usize,
+        m0: &[f64],
+        t1: &[f64],
+        t2: &[f64],
+        k: DMatrix<f64>,
+    ) -> Self {
+        let pools = m0
+            .iter()
+            .map(|&m| {
+                let mut pool = EPGVecRepresentation::new(n_states);
+                pool.z[0] = Complex64::from(m);
+                pool
+            })
+            .collect();
+
+        Self {
+            pools,
+            m0: m0.to_vec(),
+            r1: t1.iter().map(|t| 1.0 / t).collect(),
+            r2: t2.iter().map(|t| 1.0 / t).collect(),
+            k,
+        }
+    }
+
+    /// Sum of the pools' F0.
+    pub(crate) fn read(&self) -> Complex64 {
+        self.pools.iter().map(|p| p.read()).sum()
+    }
+
+    pub(crate) fn rotate(&mut self, rmat: &Array<Complex64, Ix2>) {
+        self.pools.iter_mut().for_each(|p| p.rotate(rmat));
+    }
+
+    pub(crate) fn crush(&mut self) {
+        self.pools.iter_mut().for_each(|p| p.crush());
+    }
+
+    /// Interval of length `dt` with gradient twists, relaxation and exchange.
+    pub(crate) fn grelax(&mut self, dt: f64, ntwists: i32) {
+        self.pools.iter_mut().for_each(|p| p.spoil(ntwists));
+
+        let decay = |rates: &[f64]| {
+            let mut a = self.k.clone();
+            for (ix, r) in rates.iter().enumerate() {
+                a[(ix, ix)] -= r;
+            }
+            (a * dt).exp()
+        };
+        let e2 = decay(&self.r2);
+        let e1 = decay(&self.r1);
+
+        let n = self.pools.len();
+        let mix = |e: &DMatrix<f64>, x: Vec<Complex64>| -> Vec<Complex64> {
+            (0..n)
+                .map(|i| (0..n).map(|j| e[(i, j)] * x[j]).sum())
+                .collect()
+        };
+
+        for ix in 0..self.pools[0].f_p.len() {
+            let f_p = mix(&e2, self.pools.iter().map(|p| p.f_p[ix]).collect());
+            let f_n = mix(&e2, self.pools.iter().map(|p| p.f_n[ix]).collect());
+            // the source term of the Bloch-McConnell equations only drives z0, whose
+            // equilibrium is m0 since exchange conserves it.
+            let m0 = if ix == 0 { &self.m0[..] } else { &[][..] };
+            let offset = |j: usize| m0.get(j).copied().unwrap_or(0.0);
+            let z = mix(
+                &e1,
+                self.pools
+                    .iter()
+                    .enumerate()
+                    .map(|(j, p)| p.z[ix] - offset(j))
+                    .collect(),
+            );
+
+            for (j, pool) in self.pools.iter_mut().enumerate() {
+                pool.f_p[ix] = f_p[j];
+                pool.f_n[ix] = f_n[j];
+                pool.z[ix] = z[j] + offset(j);
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use ndarray::{arr1, Array, Ix2};
+use ndarray::{Array, Ix2};
 use num_complex::{Complex, Complex64};
 
 use std::collections::VecDeque;
@@ -12,17 +12,17 @@ use crate::types::EPG;
 #[derive(Debug, PartialEq)]
 pub(crate) struct EPGVecRepresentation {
     length: usize,
-    f_p: VecDeque<Complex64>,
-    f_n: VecDeque<Complex64>,
-    z: VecDeque<Complex64>,
+    pub(super) f_p: VecDeque<Complex64>,
+    pub(super) f_n: VecDeque<Complex64>,
+    pub(super) z: VecDeque<Complex64>,
 }
 
 impl fmt::Display for EPGVecRepresentation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for ix in 0..self.length {
-            write!(
+            writeln!(
                 f,
-                "f+ {:.3}\tf- {:.3}\tz {:.3}\n",
+                "f+ {:.3}\tf- {:.3}\tz {:.3}",
                 self.f_p[ix], self.f_n[ix], self.z[ix]
             )?;
         }
@@ -59,6 +59,10 @@ impl crate::types::EPG for EPGVecRepresentation {
         self.f_p[0]
     }
 
+    fn read_mz(&self) -> Complex64 {
+        self.z[0]
+    }
+
     fn excite(&mut self) {
         // 90 degree excitation about Y to generate pure x state from [0 0 1] mz.
         let rot = gen_rotation_matrix(PI / 2.0, PI / 2.0);
@@ -73,14 +77,36 @@ impl crate::types::EPG for EPGVecRepresentation {
         gradient_shift(self, ntwists);
     }
 
+    fn crush(&mut self) {
+        // ideal spoiling: dephase all transverse states beyond recall.
+        for x in self.f_p.iter_mut().chain(self.f_n.iter_mut()) {
+            *x = Complex64::from(0.0);
+        }
+    }
+
     fn grelax(&mut self, et1d: Complex64, et2d: Complex64, ntwists: i32) {
         gradient_shift(self, ntwists);
         relaxation(self, et1d, et2d);
     }
 
-    fn delay(self: &mut Self, et1d: Complex64, et2d: Complex64) {
+    fn delay(&mut self, et1d: Complex64, et2d: Complex64) {
         relaxation(self, et1d, et2d);
     }
+
+    fn diffuse(&mut self, adc: f64, dt: f64, dk: f64, ntwists: i32) {
+        diffusion(self, adc, dt, dk, ntwists);
+    }
+
+    fn precess(&mut self, phase: f64) {
+        // off-resonance: every F state gains the phase, f_n holds conjugates.
+        let rot = (Complex::i() * phase).exp();
+        for x in self.f_p.iter_mut() {
+            *x *= rot
+        }
+        for x in self.f_n.iter_mut() {
+            *x *= rot.conj()
+        }
+    }
 }
 
 impl Default for EPGVecRepresentation {
@@ -110,9 +136,8 @@ pub(crate) fn to_mxy(epg: &EPGVecRepresentation) -> Complex64 {
 }
 
 pub(crate) fn to_mz(epg: &EPGVecRepresentation) -> Complex64 {
-    let mut mz = Complex64::from(0.0);
     // sum every element of epg.z 1..
-    mz = epg
+    let mut mz: Complex64 = epg
         .z
         .iter()
         .skip(1)
@@ -126,36 +151,59 @@ pub(crate) fn to_mz(epg: &EPGVecRepresentation) -> Complex64 {
 }
 
 fn rf_rotation(epg: &mut EPGVecRepresentation, rmat: &Array<Complex64, Ix2>) {
+    // hand-rolled 3x3 product, avoids allocating a slice per state for dot().
+    let r = |i: usize, j: usize| rmat[[i, j]];
+    let (r00, r01, r02) = (r(0, 0), r(0, 1), r(0, 2));
+    let (r10, r11, r12) = (r(1, 0), r(1, 1), r(1, 2));
+    let (r20, r21, r22) = (r(2, 0), r(2, 1), r(2, 2));
+
     for ix in 0..epg.length {
-        // we don't want this copy here but we can't dot() with references.
-        // option1 : rewrite own dot (not hard)
-        // option2 : use arr2 as underlying structure.
-        let sl = arr1(&[epg.f_p[ix], epg.f_n[ix], epg.z[ix]]);
-        let rot = rmat.dot(&sl);
-        epg.f_p[ix] = rot[0];
-        epg.f_n[ix] = rot[1];
-        epg.z[ix] = rot[2];
+        let (fp, fn_, z) = (epg.f_p[ix], epg.f_n[ix], epg.z[ix]);
+        epg.f_p[ix] = r00 * fp + r01 * fn_ + r02 * z;
+        epg.f_n[ix] = r10 * fp + r11 * fn_ + r12 * z;
+        epg.z[ix] = r20 * fp + r21 * fn_ + r22 * z;
     }
 }
 
 fn relaxation(epg: &mut EPGVecRepresentation, et1d: Complex64, et2d: Complex64) {
     for x in epg.f_n.iter_mut() {
-        *x = *x * et2d
+        *x *= et2d
     }
 
     for x in epg.f_p.iter_mut() {
-        *x = *x * et2d
+        *x *= et2d
     }
 
     for (ix, z) in epg.z.iter_mut().enumerate() {
         if ix == 0 {
             *z = (1.0 - et1d) + (*z * et1d)
         } else {
-            *z = *z * et1d
+            *z *= et1d
         }
     }
 }
 
+fn diffusion(epg: &mut EPGVecRepresentation, adc: f64, dt: f64, dk: f64, ntwists: i32) {
+    // Attenuation over an interval dt in which F states move from k to k + ntwists * dk
+    // (Weigel 2010). dk is the wavenumber of one twist in rad/m. Apply before the shift.
+    let step = ntwists as f64 * dk;
+    let f_attenuation = |k: f64| (-adc * dt * (k * k + k * step + step * step / 3.0)).exp();
+
+    for (ix, x) in epg.f_p.iter_mut().enumerate() {
+        *x *= f_attenuation(ix as f64 * dk)
+    }
+
+    // f_n[ix] holds the conjugate of F at -ix
+    for (ix, x) in epg.f_n.iter_mut().enumerate() {
+        *x *= f_attenuation(-(ix as f64) * dk)
+    }
+
+    for (ix, z) in epg.z.iter_mut().enumerate() {
+        let k = ix as f64 * dk;
+        *z *= (-adc * dt * k * k).exp()
+    }
+}
+
 // TODO: make a v2 that doesn't recurse and just shifts by multile steps
 fn gradient_shift(epg: &mut EPGVecRepresentation, ntwists: i32) {
     // Shift states.
@@ -163,16 +211,17 @@ fn gradient_shift(epg: &mut EPGVecRepresentation, ntwists: i32) {
     // F0 is special, since f_p [0] is f0, and f_n[0] is f0*(conj)
 
     match ntwists {
-        n if n == 0 => return,
+        0 => (),
         n if n > 0 => {
             // f_p becomes more positive. f_m becomes less negative
             // do shift
             let _ = epg.f_n.pop_front().unwrap(); // f0c discard
             let f1 = epg.f_n.pop_front().unwrap();
 
+            // f_n holds conjugates, F-1 becomes F0
             let f1c = f1.conj();
-            epg.f_p.push_front(f1);
-            epg.f_n.push_front(f1c);
+            epg.f_p.push_front(f1c);
+            epg.f_n.push_front(f1);
 
             // we've pop'd 2 from f_n and pushed 1. So length is one less.
             // add zero to the end.
@@ -195,7 +244,8 @@ fn gradient_shift(epg: &mut EPGVecRepresentation, ntwists: i32) {
 
             let f1c = f1.conj();
 
-            epg.f_n.push_front(f0);
+            // F0 becomes F-1, held as its conjugate
+            epg.f_n.push_front(f0.conj());
             epg.f_n.push_front(f1c);
 
             // we've pop'd 2 from f_n and pushed 1. So length is one less.
@@ -278,11 +328,7 @@ mod tests {
 
     fn test_complex_close_l1(a: &Complex64, b: &Complex64, tolerance: f64) -> bool {
         let diff = a.norm() - b.norm();
-        if diff.abs() <= tolerance {
-            return true;
-        } else {
-            return false;
-        }
+        diff.abs() <= tolerance
     }
 
     fn epg_close(epg1: &EPGVecRepresentation, epg2: &EPGVecRepresentation) {
@@ -348,6 +394,51 @@ mod tests {
         println!("signal = {:?}", signal);
         assert!(test_complex_close_l1(&expected, &signal, 1e-7));
     }
+    #[test]
+    fn test_precess_refocused_by_180() {
+        let mut epg = EPGVecRepresentation::new(3);
+        let mut epg2 = EPGVecRepresentation::new(3);
+
+        let x180 = gen_rotation_matrix(PI, PI / 2.0);
+        for e in [&mut epg, &mut epg2] {
+            e.excite();
+        }
+
+        epg.precess(0.7);
+        epg.rotate(&x180);
+        epg.precess(0.7);
+        epg2.rotate(&x180);
+
+        epg_close(&epg, &epg2);
+        assert!((epg.read() - epg2.read()).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_precess_refocused_across_gradient() {
+        let mut epg = EPGVecRepresentation::new(5);
+        let mut epg2 = EPGVecRepresentation::new(5);
+
+        let x180 = gen_rotation_matrix(PI, PI / 2.0);
+        let one = Complex64::from(1.0);
+        for e in [&mut epg, &mut epg2] {
+            e.excite();
+        }
+
+        for _ in 0..3 {
+            epg.grelax(one, one, 1);
+            epg.precess(0.7);
+            epg.rotate(&x180);
+            epg.grelax(one, one, 1);
+            epg.precess(0.7);
+
+            epg2.grelax(one, one, 1);
+            epg2.rotate(&x180);
+            epg2.grelax(one, one, 1);
+
+            assert!((epg.read() - epg2.read()).norm() < 1e-12);
+        }
+    }
+
     #[test]
     fn test_conjugate_states() {
         // test if f_p(k) == conj(f_n(k))
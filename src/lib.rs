@@ -10,7 +10,11 @@ use std::f64::consts::PI;
 mod epg;
 pub mod sequences;
 pub mod types;
-//mod tissues;
+pub mod tissues;
+pub mod phantom;
+pub mod optimize;
+pub mod noise;
+pub mod uncertainty;
 
 
 
@@ -18,16 +22,8 @@ pub mod types;
 fn demo() {
     println!("Hello, world!");
 
-    let params = sequences::space::SpaceParams {
-        etl: 220,
-        esp: 0.01,
-        t1: 0.58,
-        t2: 0.11,
-        refocus_angle: PI,
-        cpmg_phase: PI / 2.0,
-        debug_print: false,
-    };
-    let res = sequences::space::simulate(params);
+    let params = sequences::space::SpaceParams::new(220, 0.58, 0.11, 0.01, PI, PI / 2.0);
+    let _res = sequences::space::simulate(params);
     //println!("{:?}", res);
 
 }
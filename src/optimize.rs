@@ -0,0 +1,370 @@
+//! Contrast optimisation of sequence parameters between two tissues.
+
+use num_complex::Complex64;
+
+use crate::sequences::common::nelder_mead;
+use crate::sequences::Sequence;
+use crate::types::TissueProperties;
+
+/// Search range of one sequence parameter.
+#[derive(Clone, Debug)]
+pub struct Bound {
+    pub name: &'static str,
+    pub min: f64,
+    pub max: f64,
+    /// Grid points of the initial search.
+    pub steps: usize,
+    /// Integer parameters, such as the echo train length, are fixed at their best grid
+    /// value during refinement.
+    pub integer: bool,
+}
+
+impl Bound {
+    pub fn new(name: &'static str, min: f64, max: f64, steps: usize) -> Self {
+        Self {
+            name,
+            min,
+            max,
+            steps,
+            integer: false,
+        }
+    }
+
+    pub fn integer(mut self) -> Self {
+        self.integer = true;
+        self
+    }
+
+    fn clamp(&self, x: f64) -> f64 {
+        let x = x.clamp(self.min, self.max);
+        if self.integer {
+            x.round()
+        } else {
+            x
+        }
+    }
+
+    fn grid(&self) -> Vec<f64> {
+        let mut grid: Vec<f64> = (0..self.steps)
+            .map(|ix| {
+                let t = ix as f64 / (self.steps.max(2) - 1) as f64;
+                self.clamp(self.min + t * (self.max - self.min))
+            })
+            .collect();
+        grid.dedup();
+        grid
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Objective {
+    /// Signal difference `|S_a - S_b|`.
+    Contrast,
+    /// Signal difference per square root of the sequence duration, proportional to
+    /// the CNR reached in a fixed scan time.
+    CnrEfficiency,
+}
+
+/// Readout compared between the tissues.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Echo {
+    First,
+    /// Middle readout, the k-space centre of a linearly ordered train.
+    Center,
+    Last,
+}
+
+/// Objective change when one parameter moves away from the optimum.
+#[derive(Clone, Debug)]
+pub struct Sensitivity {
+    pub name: &'static str,
+    pub value: f64,
+    /// Step taken either side of the optimum, 5% of the search range.
+    pub step: f64,
+    /// Objective relative to the optimum at `value - step` and `value + step`.
+    pub minus: f64,
+    pub plus: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Optimum {
+    /// Parameter values, in the order of the bounds.
+    pub values: Vec<f64>,
+    pub score: f64,
+    pub contrast: f64,
+    pub duration: f64,
+    pub sensitivity: Vec<Sensitivity>,
+}
+
+/// Maximise `objective` between tissues `a` and `b` over the sequences made by `build`,
+/// which receives parameter values in the order of `bounds`. A grid search is refined
+/// with Nelder-Mead. Parameters failing `Sequence::validate`, or for which either tissue
+/// cannot be simulated, are skipped. A bound with `min == max` holds its parameter
+/// fixed. `None` is returned if a bound has no grid points or an inverted or non-finite
+/// range, or if no grid point is valid.
+pub fn optimize<S: Sequence, F: Fn(&[f64]) -> S>(
+    build: F,
+    bounds: &[Bound],
+    a: &TissueProperties,
+    b: &TissueProperties,
+    objective: Objective,
+    echo: Echo,
+) -> Option<Optimum> {
+    // (score, contrast, duration), None for invalid parameters
+    let evaluate = |x: &[f64]| -> Option<(f64, f64, f64)> {
+        let sequence = build(x);
+
+        let pick = |signal: Vec<Complex64>| match echo {
+            Echo::First => signal[0],
+            Echo::Center => signal[signal.len() / 2],
+            Echo::Last => signal[signal.len() - 1],
+        };
+        let (sa, sb) = (sequence.simulate(a).ok()?, sequence.simulate(b).ok()?);
+        let contrast = (pick(sa) - pick(sb)).norm();
+        let duration = sequence.duration();
+        let score = match objective {
+            Objective::Contrast => contrast,
+            Objective::CnrEfficiency => contrast / duration.sqrt(),
+        };
+        Some((score, contrast, duration))
+    };
+
+    let degenerate = |b: &Bound| {
+        b.steps == 0 || !b.min.is_finite() || !b.max.is_finite() || b.min > b.max
+    };
+    if bounds.iter().any(degenerate) {
+        return None;
+    }
+
+    // grid search over every combination of grid points
+    let grids: Vec<Vec<f64>> = bounds.iter().map(Bound::grid).collect();
+    let mut best: Option<(Vec<f64>, f64)> = None;
+    let mut index = vec![0; bounds.len()];
+    loop {
+        let x: Vec<f64> = index.iter().zip(&grids).map(|(&ix, g)| g[ix]).collect();
+        if let Some((score, _, _)) = evaluate(&x) {
+            if best.as_ref().is_none_or(|(_, s)| score > *s) {
+                best = Some((x, score));
+            }
+        }
+
+        let mut dim = 0;
+        while dim < index.len() {
+            index[dim] += 1;
+            if index[dim] < grids[dim].len() {
+                break;
+            }
+            index[dim] = 0;
+            dim += 1;
+        }
+        if dim == index.len() {
+            break;
+        }
+    }
+    let (start, _) = best?;
+
+    // refine the continuous parameters, in units of their search range
+    let free: Vec<usize> = (0..bounds.len())
+        .filter(|&d| !bounds[d].integer && bounds[d].max > bounds[d].min)
+        .collect();
+    let to_values = |u: &[f64]| -> Vec<f64> {
+        let mut x = start.clone();
+        for (&d, u) in free.iter().zip(u) {
+            x[d] = bounds[d].clamp(bounds[d].min + u * (bounds[d].max - bounds[d].min));
+        }
+        x
+    };
+    let u0: Vec<f64> = free
+        .iter()
+        .map(|&d| (start[d] - bounds[d].min) / (bounds[d].max - bounds[d].min))
+        .collect();
+    let step: Vec<f64> = free
+        .iter()
+        .map(|&d| 0.5 / (bounds[d].steps.max(2) - 1) as f64)
+        .collect();
+
+    let values = if free.is_empty() {
+        start.clone()
+    } else {
+        let u = nelder_mead(
+            |u| evaluate(&to_values(u)).map_or(f64::MAX, |(score, _, _)| -score),
+            &u0,
+            &step,
+            60 * free.len(),
+        );
+        let refined = to_values(&u);
+        match (evaluate(&refined), evaluate(&start)) {
+            (Some((r, _, _)), Some((s, _, _))) if r >= s => refined,
+            _ => start.clone(),
+        }
+    };
+
+    let (score, contrast, duration) = evaluate(&values)?;
+
+    let sensitivity = bounds
+        .iter()
+        .enumerate()
+        .map(|(d, bound)| {
+            let mut step = 0.05 * (bound.max - bound.min);
+            if bound.integer {
+                step = step.round().max(1.0);
+            }
+            let relative = |delta: f64| {
+                let mut x = values.clone();
+                x[d] = bound.clamp(x[d] + delta);
+                evaluate(&x).map_or(f64::NAN, |(s, _, _)| s / score)
+            };
+            Sensitivity {
+                name: bound.name,
+                value: values[d],
+                step,
+                minus: relative(-step),
+                plus: relative(step),
+            }
+        })
+        .collect();
+
+    Some(Optimum {
+        values,
+        score,
+        contrast,
+        duration,
+        sensitivity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::irfse::{Inversion, IrFseParams};
+    use crate::sequences::{fse::FseParams, se::SeParams};
+    use crate::tissues::{get_tissue, FieldStrength};
+    use crate::types::Tissue;
+    use std::f64::consts::PI;
+
+    fn tissue(t2: f64) -> TissueProperties {
+        TissueProperties {
+            name: "t2".into(),
+            pd: 1.0,
+            t2,
+            ..get_tissue(Tissue::WhiteMatter, FieldStrength::T3)
+        }
+    }
+
+    #[test]
+    fn test_spin_echo_contrast_optimum() {
+        let build = |x: &[f64]| SeParams {
+            t1: 1.0,
+            t2: 0.1,
+            refocus_angle: PI,
+            refocus_phase: PI / 2.0,
+            echo_time: x[0],
+            debug_print: false,
+        };
+        let bounds = [Bound::new("TE", 0.01, 0.3, 16)];
+        let res = optimize(
+            build,
+            &bounds,
+            &tissue(0.07),
+            &tissue(0.1),
+            Objective::Contrast,
+            Echo::First,
+        )
+        .unwrap();
+
+        // d/dTE of exp(-TE / T2a) - exp(-TE / T2b) vanishes here
+        let expected = (0.1_f64 / 0.07).ln() / (1.0 / 0.07 - 1.0 / 0.1);
+        assert!((res.values[0] - expected).abs() < 1e-3);
+        assert!(res.sensitivity[0].minus < 1.0 && res.sensitivity[0].plus < 1.0);
+    }
+
+    #[test]
+    fn test_degenerate_bounds() {
+        let build = |x: &[f64]| SeParams {
+            t1: 1.0,
+            t2: 0.1,
+            refocus_angle: PI,
+            refocus_phase: PI / 2.0,
+            echo_time: x[0],
+            debug_print: false,
+        };
+        let run = |bound: Bound| {
+            optimize(
+                build,
+                &[bound],
+                &tissue(0.07),
+                &tissue(0.1),
+                Objective::Contrast,
+                Echo::First,
+            )
+        };
+        assert!(run(Bound::new("TE", 0.01, 0.3, 0)).is_none());
+        assert!(run(Bound::new("TE", 0.3, 0.01, 16)).is_none());
+        assert!(run(Bound::new("TE", 0.01, f64::NAN, 16)).is_none());
+
+        // an empty range holds the parameter fixed
+        let fixed = run(Bound::new("TE", 0.05, 0.05, 16)).unwrap();
+        assert_eq!(fixed.values[0], 0.05);
+        assert!(fixed.score.is_finite());
+    }
+
+    #[test]
+    fn test_cnr_efficiency_with_integer_etl() {
+        let build = |x: &[f64]| FseParams {
+            tr: x[0],
+            nshots: 2,
+            ..FseParams::new(x[1] as usize, 1.0, 0.1, 0.01, PI, PI / 2.0)
+        };
+        let bounds = [
+            Bound::new("TR", 0.5, 4.0, 8),
+            Bound::new("ETL", 4.0, 32.0, 8).integer(),
+        ];
+        let res = optimize(
+            build,
+            &bounds,
+            &tissue(0.07),
+            &tissue(0.1),
+            Objective::CnrEfficiency,
+            Echo::Last,
+        )
+        .unwrap();
+
+        assert_eq!(res.values[1].fract(), 0.0);
+        assert!(res.duration > 0.0 && res.score > 0.0);
+        assert!(res
+            .sensitivity
+            .iter()
+            .all(|s| s.minus <= 1.0 + 1e-9 && s.plus <= 1.0 + 1e-9));
+    }
+
+    #[test]
+    fn test_inversion_time_optimum() {
+        // single shot from equilibrium, the echoes scale with 1 - 2 exp(-TI / T1)
+        let build = |x: &[f64]| IrFseParams {
+            etl: 4,
+            t1: 1.0,
+            t2: 0.1,
+            esp: 0.01,
+            refocus_angle: PI,
+            cpmg_phase: PI / 2.0,
+            inversion: Inversion::Ideal,
+            inversion_time: x[0],
+            tr: 5.0,
+            nshots: 1,
+            debug_print: false,
+        };
+        let t1 = |t1: f64| TissueProperties {
+            t1,
+            ..tissue(0.1)
+        };
+        let bounds = [Bound::new("TI", 0.1, 3.0, 16)];
+        let res = optimize(build, &bounds, &t1(0.8), &t1(1.2), Objective::Contrast, Echo::First)
+            .unwrap();
+
+        // d/dTI of exp(-TI / T1b) - exp(-TI / T1a) vanishes here
+        let expected = (1.2_f64 / 0.8).ln() / (1.0 / 0.8 - 1.0 / 1.2);
+        assert!((res.values[0] - expected).abs() < 1e-3);
+        let peak = 2.0 * ((-expected / 1.2).exp() - (-expected / 0.8).exp());
+        assert!((res.contrast - peak * (-0.01_f64 / 0.1).exp()).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,172 @@
+//! Digital phantoms: contrast-weighted images synthesised from tissue label maps.
+
+use ndarray::{ArrayD, IxDyn};
+use num_complex::Complex64;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::sequences::Sequence;
+use crate::types::TissueProperties;
+
+pub mod reference;
+
+/// Real-valued representation of the complex images.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    Magnitude,
+    Phase,
+    Real,
+}
+
+impl Output {
+    fn apply(&self, s: Complex64) -> f64 {
+        match self {
+            Output::Magnitude => s.norm(),
+            Output::Phase => s.arg(),
+            Output::Real => s.re,
+        }
+    }
+}
+
+/// 2D or 3D label image. Labels without a tissue are background and read as zero.
+pub struct Phantom {
+    labels: ArrayD<u32>,
+    tissues: BTreeMap<u32, TissueProperties>,
+}
+
+impl Phantom {
+    pub fn new(labels: ArrayD<u32>) -> Self {
+        Self {
+            labels,
+            tissues: BTreeMap::new(),
+        }
+    }
+
+    /// Load the labels from the grey levels of an 8-bit image, indexed [row, column].
+    pub fn from_png(path: &Path) -> Result<Self, image::ImageError> {
+        let img = image::open(path)?.into_luma8();
+        let (width, height) = img.dimensions();
+        let labels = ArrayD::from_shape_fn(IxDyn(&[height as usize, width as usize]), |ix| {
+            img.get_pixel(ix[1] as u32, ix[0] as u32)[0] as u32
+        });
+        Ok(Self::new(labels))
+    }
+
+    /// Assign `tissue` to every pixel labelled `label`.
+    pub fn with_tissue(mut self, label: u32, tissue: TissueProperties) -> Self {
+        self.tissues.insert(label, tissue);
+        self
+    }
+
+    pub fn labels(&self) -> &ArrayD<u32> {
+        &self.labels
+    }
+
+    /// Complex image of every readout of `sequence`. Each labelled tissue is simulated
+    /// once and scattered into the image. Fails if the sequence or a tissue is invalid.
+    pub fn synthesize(&self, sequence: &dyn Sequence) -> Result<Vec<ArrayD<Complex64>>, String> {
+        let signals: BTreeMap<u32, Vec<Complex64>> = self
+            .tissues
+            .iter()
+            .map(|(&label, tissue)| Ok((label, sequence.simulate(tissue)?)))
+            .collect::<Result<_, String>>()?;
+        let nreads = signals.values().map(|s| s.len()).max().unwrap_or(0);
+
+        Ok((0..nreads)
+            .map(|echo| {
+                self.labels.map(|label| match signals.get(label) {
+                    Some(signal) => signal[echo],
+                    None => Complex64::from(0.0),
+                })
+            })
+            .collect())
+    }
+
+    /// Real-valued images of every readout of `sequence`.
+    pub fn synthesize_as(
+        &self,
+        sequence: &dyn Sequence,
+        output: Output,
+    ) -> Result<Vec<ArrayD<f64>>, String> {
+        Ok(self
+            .synthesize(sequence)?
+            .iter()
+            .map(|image| image.map(|&s| output.apply(s)))
+            .collect())
+    }
+}
+
+/// Save a 2D image as an 8-bit PNG, scaled so its maximum is white and negative values clip.
+pub fn save_png(image: &ArrayD<f64>, path: &Path) -> Result<(), image::ImageError> {
+    assert_eq!(image.ndim(), 2, "only 2D images can be saved");
+
+    let (height, width) = (image.shape()[0], image.shape()[1]);
+    let max = image.iter().cloned().fold(0.0, f64::max);
+    let scale = if max > 0.0 { 255.0 / max } else { 0.0 };
+
+    let img = image::GrayImage::from_fn(width as u32, height as u32, |x, y| {
+        let value = image[[y as usize, x as usize]] * scale;
+        image::Luma([value.clamp(0.0, 255.0).round() as u8])
+    });
+    img.save(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::se::SeParams;
+    use crate::tissues::{get_tissue, FieldStrength};
+    use crate::types::Tissue;
+    use ndarray::arr2;
+    use std::f64::consts::PI;
+
+    fn spin_echo() -> SeParams {
+        SeParams {
+            t1: 1.0,
+            t2: 0.1,
+            refocus_angle: PI,
+            refocus_phase: PI / 2.0,
+            echo_time: 0.1,
+            debug_print: false,
+        }
+    }
+
+    #[test]
+    fn test_scatter_tissue_signals() {
+        let labels = arr2(&[[0, 1, 1], [2, 2, 0]]).into_dyn();
+        let phantom = Phantom::new(labels)
+            .with_tissue(1, get_tissue(Tissue::WhiteMatter, FieldStrength::T3))
+            .with_tissue(2, get_tissue(Tissue::CerebroSpinalFluid, FieldStrength::T3));
+
+        let images = phantom.synthesize_as(&spin_echo(), Output::Magnitude).unwrap();
+        assert_eq!(images.len(), 1);
+
+        let image = &images[0];
+        let wm = 0.69 * (-0.1_f64 / 0.069).exp();
+        let csf = (-0.1_f64 / 1.8).exp();
+        assert_eq!(image[[0, 0]], 0.0);
+        assert!((image[[0, 2]] - wm).abs() < 1e-9);
+        assert!((image[[1, 0]] - csf).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_png_labels() {
+        // unique per run, so concurrent test runs do not share the file
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let name = format!("epg_phantom_labels_{}_{}.png", std::process::id(), nanos);
+        let path = std::env::temp_dir().join(name);
+        image::GrayImage::from_fn(4, 2, |x, _| image::Luma([(x % 2) as u8]))
+            .save(&path)
+            .unwrap();
+
+        let phantom = Phantom::from_png(&path);
+        std::fs::remove_file(&path).unwrap();
+        let phantom = phantom.unwrap();
+        assert_eq!(phantom.labels().shape(), &[2, 4]);
+        assert_eq!(phantom.labels()[[1, 3]], 1);
+    }
+}
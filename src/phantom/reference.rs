@@ -0,0 +1,231 @@
+//! Built-in reference phantoms with their layout.
+//!
+//! Images span a field of view of [-1, 1] in both directions, indexed [row, column]
+//! with the first row at the top.
+
+use ndarray::{Array2, ArrayD};
+
+use super::Phantom;
+use crate::tissues::{get_tissue, FieldStrength};
+use crate::types::{Tissue, TissueProperties};
+
+/// Sphere arrays of the NIST/ISMRM system phantom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NistArray {
+    /// NiCl2 spheres, spanning T1.
+    T1,
+    /// MnCl2 spheres, spanning T2.
+    T2,
+}
+
+// Approximate nominal values of the NIST/ISMRM phantom (version 1) at 3 T and 20 C in
+// ms, sphere 1 first. They are not the values of a calibration certificate.
+const APPROX_NICL2_T1_3T: [f64; 14] = [
+    1989.0, 1454.0, 984.1, 706.0, 496.7, 351.5, 247.13, 175.3, 125.9, 89.0, 62.7, 44.53, 30.84,
+    21.719,
+];
+const APPROX_MNCL2_T1_3T: [f64; 14] = [
+    2480.0, 2173.0, 1907.0, 1604.0, 1332.0, 1044.0, 801.7, 608.6, 458.4, 336.5, 244.2, 176.6,
+    126.9, 90.9,
+];
+const APPROX_MNCL2_T2_3T: [f64; 14] = [
+    645.8, 423.6, 286.0, 184.8, 134.1, 94.4, 62.5, 45.0, 31.0, 20.1, 15.4, 10.9, 7.8, 5.6,
+];
+
+/// Assumed ratio of NiCl2 transverse to longitudinal relaxivity, giving an approximate
+/// T2 for the T1 array.
+const APPROX_NICL2_R2_R1: f64 = 1.2;
+
+/// Approximate spheres 1 to 14 of `array`, named `approx-nist-t1-1` etc. Suitable for
+/// contrast studies, use the calibration certificate of a particular phantom for
+/// quantitative validation.
+///
+/// NiCl2 relaxation barely depends on the field, so the T1 array is given at 1.5 T and
+/// 3 T with the same values. The MnCl2 array is only given at 3 T. `None` for other
+/// fields.
+pub fn approximate_nist_spheres(
+    array: NistArray,
+    field: FieldStrength,
+) -> Option<Vec<TissueProperties>> {
+    match (array, field) {
+        (NistArray::T1, FieldStrength::T1_5 | FieldStrength::T3) => (),
+        (NistArray::T2, FieldStrength::T3) => (),
+        _ => return None,
+    }
+
+    let spheres = (0..14)
+        .map(|ix| {
+            let (prefix, t1, t2) = match array {
+                NistArray::T1 => {
+                    let t1 = APPROX_NICL2_T1_3T[ix] * 1e-3;
+                    ("approx-nist-t1", t1, t1 / APPROX_NICL2_R2_R1)
+                }
+                NistArray::T2 => (
+                    "approx-nist-t2",
+                    APPROX_MNCL2_T1_3T[ix] * 1e-3,
+                    APPROX_MNCL2_T2_3T[ix] * 1e-3,
+                ),
+            };
+            TissueProperties {
+                name: format!("{}-{}", prefix, ix + 1),
+                pd: 1.0,
+                t1,
+                t2,
+                t2s: t2,
+                adc: 2.0e-9,
+                compartments: vec![],
+                exchange: vec![],
+            }
+        })
+        .collect();
+    Some(spheres)
+}
+
+/// Centre (x, y) of each sphere, sphere 1 first. Spheres 1 to 10 run clockwise from the
+/// top of an outer ring and 11 to 14 sit on an inner square.
+pub fn nist_layout() -> Vec<(f64, f64)> {
+    let ring = (0..10).map(|ix| {
+        let angle = std::f64::consts::PI / 2.0 - ix as f64 * std::f64::consts::PI / 5.0;
+        (0.55 * angle.cos(), 0.55 * angle.sin())
+    });
+    let inner = [(-0.2, 0.2), (0.2, 0.2), (0.2, -0.2), (-0.2, -0.2)];
+    ring.chain(inner).collect()
+}
+
+/// Radius of the NIST spheres in the field of view.
+pub const NIST_SPHERE_RADIUS: f64 = 0.1;
+
+/// `size` by `size` slice through the centre of a NIST sphere array with the values of
+/// `approximate_nist_spheres`, sphere `n` labelled `n`. The fill between spheres is
+/// background. `None` where the array has no values at `field`.
+pub fn approximate_nist_phantom(
+    array: NistArray,
+    field: FieldStrength,
+    size: usize,
+) -> Option<Phantom> {
+    let spheres = approximate_nist_spheres(array, field)?;
+    let centres = nist_layout();
+    let labels = grid(size, |x, y| {
+        centres
+            .iter()
+            .position(|(cx, cy)| (x - cx).powi(2) + (y - cy).powi(2) <= NIST_SPHERE_RADIUS.powi(2))
+            .map_or(0, |ix| ix as u32 + 1)
+    });
+
+    let phantom = spheres
+        .into_iter()
+        .enumerate()
+        .fold(Phantom::new(labels), |phantom, (ix, tissue)| {
+            phantom.with_tissue(ix as u32 + 1, tissue)
+        });
+    Some(phantom)
+}
+
+/// Ellipses of the modified Shepp-Logan phantom (Toft), as
+/// (label, a, b, x0, y0, rotation in degrees). Later ellipses paint over earlier ones.
+const SHEPP_LOGAN: [(u32, f64, f64, f64, f64, f64); 10] = [
+    (1, 0.69, 0.92, 0.0, 0.0, 0.0),
+    (2, 0.6624, 0.874, 0.0, -0.0184, 0.0),
+    (3, 0.11, 0.31, 0.22, 0.0, -18.0),
+    (3, 0.16, 0.41, -0.22, 0.0, 18.0),
+    (4, 0.21, 0.25, 0.0, 0.35, 0.0),
+    (5, 0.046, 0.046, 0.0, 0.1, 0.0),
+    (5, 0.046, 0.046, 0.0, -0.1, 0.0),
+    (6, 0.046, 0.023, -0.08, -0.605, 0.0),
+    (6, 0.023, 0.023, 0.0, -0.606, 0.0),
+    (6, 0.023, 0.046, 0.06, -0.605, 0.0),
+];
+
+/// `size` by `size` modified Shepp-Logan head with tissues at `field`. The skull (1)
+/// has no signal, the brain (2) is grey matter, the ventricles (3) CSF, the upper
+/// ellipse (4) white matter, the central spots (5) thalamus and the lower spots (6) blood.
+pub fn shepp_logan(field: FieldStrength, size: usize) -> Phantom {
+    let labels = grid(size, |x, y| {
+        SHEPP_LOGAN
+            .iter()
+            .rev()
+            .find(|(_, a, b, x0, y0, phi)| {
+                let (s, c) = phi.to_radians().sin_cos();
+                let (dx, dy) = (x - x0, y - y0);
+                let (u, v) = (dx * c + dy * s, -dx * s + dy * c);
+                (u / a).powi(2) + (v / b).powi(2) <= 1.0
+            })
+            .map_or(0, |e| e.0)
+    });
+
+    Phantom::new(labels)
+        .with_tissue(2, get_tissue(Tissue::GreyMatter, field))
+        .with_tissue(3, get_tissue(Tissue::CerebroSpinalFluid, field))
+        .with_tissue(4, get_tissue(Tissue::WhiteMatter, field))
+        .with_tissue(5, get_tissue(Tissue::Thalamus, field))
+        .with_tissue(6, get_tissue(Tissue::Blood, field))
+}
+
+/// Label image from the label at each pixel centre (x, y).
+fn grid<F: Fn(f64, f64) -> u32>(size: usize, label: F) -> ArrayD<u32> {
+    let coord = |ix: usize| (2 * ix + 1) as f64 / size as f64 - 1.0;
+    Array2::from_shape_fn((size, size), |(row, col)| label(coord(col), -coord(row))).into_dyn()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phantom::Output;
+    use crate::sequences::se::SeParams;
+    use std::f64::consts::PI;
+
+    fn pixel(size: usize, (x, y): (f64, f64)) -> [usize; 2] {
+        let ix = |c: f64| ((c + 1.0) / 2.0 * size as f64) as usize;
+        [ix(-y), ix(x)]
+    }
+
+    #[test]
+    fn test_nist_t2_array_spin_echo() {
+        let phantom = approximate_nist_phantom(NistArray::T2, FieldStrength::T3, 128).unwrap();
+        let se = SeParams {
+            t1: 1.0,
+            t2: 0.1,
+            refocus_angle: PI,
+            refocus_phase: PI / 2.0,
+            echo_time: 0.02,
+            debug_print: false,
+        };
+        let image = &phantom.synthesize_as(&se, Output::Magnitude).unwrap()[0];
+
+        for (n, centre) in nist_layout().into_iter().enumerate() {
+            let p = pixel(128, centre);
+            assert_eq!(phantom.labels()[p], n as u32 + 1);
+            let expected = (-20.0 / APPROX_MNCL2_T2_3T[n]).exp();
+            assert!((image[p] - expected).abs() < 1e-9);
+        }
+        assert_eq!(image[[0, 0]], 0.0);
+    }
+
+    #[test]
+    fn test_nist_field_availability() {
+        let t1_15 = approximate_nist_spheres(NistArray::T1, FieldStrength::T1_5).unwrap();
+        let t1_3 = approximate_nist_spheres(NistArray::T1, FieldStrength::T3).unwrap();
+        assert_eq!(t1_15[0].t1, t1_3[0].t1);
+        assert_eq!(t1_3[13].name, "approx-nist-t1-14");
+
+        assert!(approximate_nist_spheres(NistArray::T2, FieldStrength::T3).is_some());
+        assert!(approximate_nist_spheres(NistArray::T2, FieldStrength::T1_5).is_none());
+        for field in [FieldStrength::T0_55, FieldStrength::T7] {
+            assert!(approximate_nist_spheres(NistArray::T1, field).is_none());
+            assert!(approximate_nist_phantom(NistArray::T2, field, 16).is_none());
+        }
+    }
+
+    #[test]
+    fn test_shepp_logan_labels() {
+        let phantom = shepp_logan(FieldStrength::T3, 256);
+        let labels = phantom.labels();
+        assert_eq!(labels[pixel(256, (0.0, 0.95))], 0);
+        assert_eq!(labels[pixel(256, (0.0, 0.9))], 1);
+        assert_eq!(labels[pixel(256, (0.0, 0.7))], 2);
+        assert_eq!(labels[pixel(256, (0.22, 0.0))], 3);
+        assert_eq!(labels[pixel(256, (0.0, 0.35))], 4);
+        assert_eq!(labels[pixel(256, (0.0, 0.1))], 5);
+        assert_eq!(labels[pixel(256, (0.0, -0.606))], 6);
+    }
+}
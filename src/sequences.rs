@@ -1,13 +1,729 @@
+use num_complex::Complex64;
+
+use crate::types::{Compartment, TissueProperties};
+
+pub(crate) mod common;
 pub mod fse;
 pub mod se;
 pub mod fid;
 pub mod space;
+pub mod mprage;
+pub mod mp2rage;
+pub mod irfse;
+pub mod steam;
+pub mod mrf;
+pub mod grase;
+pub mod looklocker;
+pub mod molli;
+pub mod afi;
+pub mod dam;
+pub mod despot1;
+pub mod despot2;
+pub mod t2prep;
+pub mod fat;
+pub mod qalas;
+pub mod dwse;
+pub mod dwsteam;
+pub mod dephasing;
+pub mod gre;
+pub mod dce;
+pub mod prepared;
+
+pub use common::Spoiling;
+pub use prepared::{Preparation, Prepared};
 
 pub enum SequenceSelection {
     FSE(fse::FseParams),
     SE(se::SeParams),
     FID(fid::FidParams),
     SPACE(space::SpaceParams),
+    MPRAGE(mprage::MprageParams),
+    MP2RAGE(mp2rage::Mp2rageParams),
+    IRFSE(irfse::IrFseParams),
+    STEAM(steam::SteamParams),
+    MRF(mrf::MrfParams),
+    GRASE(grase::GraseParams),
+    LookLocker(looklocker::LookLockerParams),
+    MOLLI(molli::MolliParams),
+    AFI(afi::AfiParams),
+    DAM(dam::DamParams),
+    DESPOT1(despot1::Despot1Params),
+    DESPOT2(despot2::Despot2Params),
+    T2Prep(t2prep::T2PrepParams),
+    FatSat(fat::FatSatParams),
+    QALAS(qalas::QalasParams),
+    DWSE(dwse::DwSeParams),
+    DWSTEAM(dwsteam::DwSteamParams),
+    GRE(gre::GreParams),
+    DCE(dce::DceParams),
+    }
+
+/// Scanner event of a sequence, used to inspect timing without simulating.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// Instantaneous RF pulse.
+    Pulse { time: f64, flip_angle: f64, phase: f64 },
+    /// Dephasing gradient of `ntwists` twists over `duration`.
+    Gradient { time: f64, duration: f64, ntwists: i32 },
+    /// Spoiler removing all transverse magnetization.
+    Crusher { time: f64 },
+    /// -90 degree pulse phased to the current echo, storing it along +z.
+    Restore { time: f64 },
+    /// Magnetization back at equilibrium, starting an independent acquisition.
+    Reset { time: f64 },
+    /// Reported readout, demodulated by the receiver `phase`. A gradient echo read `tau`
+    /// seconds away from its spin echo or excitation decays with T2' over `tau`.
+    Readout { time: f64, phase: f64, tau: f64 },
+}
+
+impl Event {
+    pub fn time(&self) -> f64 {
+        match *self {
+            Event::Pulse { time, .. }
+            | Event::Gradient { time, .. }
+            | Event::Crusher { time }
+            | Event::Restore { time }
+            | Event::Reset { time }
+            | Event::Readout { time, .. } => time,
+        }
+    }
+
+    /// The same event `dt` seconds later.
+    pub fn delayed(&self, dt: f64) -> Event {
+        let mut event = self.clone();
+        match &mut event {
+            Event::Pulse { time, .. }
+            | Event::Gradient { time, .. }
+            | Event::Crusher { time }
+            | Event::Restore { time }
+            | Event::Reset { time }
+            | Event::Readout { time, .. } => *time += dt,
+        }
+        event
+    }
+}
+
+/// Common interface of the sequences that can be driven through `SequenceSelection`.
+pub trait Sequence {
+    fn name(&self) -> &'static str;
+
+    /// Check the parameters describe a playable sequence.
+    fn validate(&self) -> Result<(), String>;
+
+    /// Check `tissue` can be simulated by this sequence.
+    fn validate_tissue(&self, tissue: &TissueProperties) -> Result<(), String> {
+        tissue.validate()
+    }
+
+    /// Check playing the events reproduces the signal of `tissue`, as needed for
+    /// exchange and preparations. Events carry no diffusion or time-varying relaxation,
+    /// and T2' dephasing of gradient echoes is not applied on exchange graphs.
+    fn validate_events(&self, tissue: &TissueProperties) -> Result<(), String> {
+        let exchanging = !tissue.exchange.is_empty();
+        let dephased = exchanging && tissue.pools().iter().any(|c| c.t2s < c.t2);
+        let gradient_echo = self
+            .events()
+            .iter()
+            .any(|e| matches!(e, Event::Readout { tau, .. } if *tau != 0.0));
+        if dephased && gradient_echo {
+            return Err(format!("{}: T2' dephasing with exchange is not modelled", tissue.name));
+        }
+        Ok(())
+    }
+
+    /// Events of every simulated shot, in time order. Only the readouts returned by
+    /// `signal` are included.
+    fn events(&self) -> Vec<Event>;
+
+    /// Total time spanned by the events.
+    fn duration(&self) -> f64;
+
+    /// Signal at each readout of a single compartment with unit proton density. The
+    /// relaxation times, and T2* where gradient echoes are read, replace those in the
+    /// parameters.
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64>;
+
+    /// Signal at each readout for `tissue`, scaled by its proton density. Compartments
+    /// are summed by volume fraction. With exchange the events are played on coupled
+    /// phase graphs instead.
+    fn simulate(&self, tissue: &TissueProperties) -> Result<Vec<Complex64>, String> {
+        self.validate()?;
+        self.validate_tissue(tissue)?;
+        if !tissue.exchange.is_empty() {
+            self.validate_events(tissue)?;
+        }
+
+        let signal = if tissue.exchange.is_empty() {
+            let mut weighted = tissue.pools().into_iter().map(|pool| {
+                let signal = self.signal(&pool);
+                signal.into_iter().map(|s| pool.fraction * s).collect::<Vec<_>>()
+            });
+            let first = weighted.next().expect("a tissue has at least one pool");
+            weighted.fold(first, |mut signal, pool_signal| {
+                for (s, p) in signal.iter_mut().zip(pool_signal) {
+                    *s += p;
+                }
+                signal
+            })
+        } else {
+            common::play_exchange(&self.events(), tissue)
+        };
+
+        Ok(signal.iter().map(|s| tissue.pd * s).collect())
+    }
+
+    /// Times of the readout events.
+    fn readout_times(&self) -> Vec<f64> {
+        self.events()
+            .iter()
+            .filter(|e| matches!(e, Event::Readout { .. }))
+            .map(Event::time)
+            .collect()
     }
+}
 
+impl SequenceSelection {
+    /// The selected sequence.
+    pub fn as_sequence(&self) -> &dyn Sequence {
+        match self {
+            SequenceSelection::FSE(p) => p,
+            SequenceSelection::SE(p) => p,
+            SequenceSelection::FID(p) => p,
+            SequenceSelection::SPACE(p) => p,
+            SequenceSelection::MPRAGE(p) => p,
+            SequenceSelection::MP2RAGE(p) => p,
+            SequenceSelection::IRFSE(p) => p,
+            SequenceSelection::STEAM(p) => p,
+            SequenceSelection::MRF(p) => p,
+            SequenceSelection::GRASE(p) => p,
+            SequenceSelection::LookLocker(p) => p,
+            SequenceSelection::MOLLI(p) => p,
+            SequenceSelection::AFI(p) => p,
+            SequenceSelection::DAM(p) => p,
+            SequenceSelection::DESPOT1(p) => p,
+            SequenceSelection::DESPOT2(p) => p,
+            SequenceSelection::T2Prep(p) => p,
+            SequenceSelection::FatSat(p) => p,
+            SequenceSelection::QALAS(p) => p,
+            SequenceSelection::DWSE(p) => p,
+            SequenceSelection::DWSTEAM(p) => p,
+            SequenceSelection::GRE(p) => p,
+            SequenceSelection::DCE(p) => p,
+        }
+    }
+}
+
+/// Validate and simulate the selected sequence.
+pub fn simulate(
+    selection: &SequenceSelection,
+    tissue: &TissueProperties,
+) -> Result<Vec<Complex64>, String> {
+    selection.as_sequence().simulate(tissue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tissues::{get_tissue, FieldStrength};
+    use crate::types::{Exchange, Tissue};
+    use std::f64::consts::PI;
+
+    fn tissue() -> TissueProperties {
+        TissueProperties {
+            name: "test".into(),
+            pd: 0.8,
+            t1: 1.0,
+            t2: 0.1,
+            t2s: 0.05,
+            adc: 1e-9,
+            compartments: vec![],
+            exchange: vec![],
+        }
+    }
+
+    fn myelinated(rate: f64) -> TissueProperties {
+        let compartment = |name: &str, fraction: f64, t2: f64| Compartment {
+            name: name.into(),
+            fraction,
+            t1: 1.0,
+            t2,
+            t2s: t2,
+            adc: 1e-9,
+        };
+        TissueProperties {
+            compartments: vec![
+                compartment("myelin", 0.15, 0.015),
+                compartment("axonal", 0.85, 0.08),
+            ],
+            exchange: vec![Exchange {
+                from: 0,
+                to: 1,
+                rate,
+            }],
+            ..tissue()
+        }
+    }
 
+    fn cpmg(refocus_angle: f64) -> fse::FseParams {
+        fse::FseParams::new(32, 1.0, 0.1, 0.005, refocus_angle, PI / 2.0)
+    }
+
+    #[test]
+    fn test_compartments_sum_by_fraction() {
+        let tissue = TissueProperties {
+            exchange: vec![],
+            ..myelinated(0.0)
+        };
+        let signal = cpmg(PI).simulate(&tissue).unwrap();
+        for (ix, s) in signal.iter().enumerate() {
+            let t = (ix + 1) as f64 * 0.005;
+            let expected = 0.8 * (0.15 * (-t / 0.015).exp() + 0.85 * (-t / 0.08).exp());
+            assert!((s.norm() - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_exchange_graph_matches_independent_pools() {
+        let params = cpmg(150.0_f64.to_radians());
+        let coupled = params.simulate(&myelinated(0.0)).unwrap();
+        let independent = params
+            .simulate(&TissueProperties {
+                exchange: vec![],
+                ..myelinated(0.0)
+            })
+            .unwrap();
+        for (c, i) in coupled.iter().zip(independent.iter()) {
+            assert!((c - i).norm() < 1e-7);
+        }
+    }
+
+    #[test]
+    fn test_fast_exchange_averages_t2() {
+        let signal = cpmg(PI).simulate(&myelinated(1e5)).unwrap();
+        let r2: f64 = 0.15 / 0.015 + 0.85 / 0.08;
+        let t = 32.0 * 0.005;
+        assert!((signal[31].norm() - 0.8 * (-t * r2).exp()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_fid_decays_with_t2_star() {
+        let fid = fid::FidParams {
+            nreads: 10,
+            t1: 1.0,
+            t2: 0.1,
+            echo_time: 0.005,
+            dephasing: dephasing::Dephasing::None,
+            debug_print: false,
+        };
+        let signal = fid.simulate(&tissue()).unwrap();
+        for (ix, s) in signal.iter().enumerate() {
+            let t = (ix + 1) as f64 * 0.005;
+            assert!((s.norm() - 0.8 * (-t / 0.05).exp()).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_exchange_rejects_t2_prime() {
+        let fid = fid::FidParams {
+            nreads: 4,
+            t1: 1.0,
+            t2: 0.1,
+            echo_time: 0.005,
+            dephasing: dephasing::Dephasing::None,
+            debug_print: false,
+        };
+        assert!(fid.simulate(&myelinated(4.0)).is_ok());
+
+        let mut dephased = myelinated(4.0);
+        dephased.compartments[1].t2s = 0.05;
+        let err = fid.simulate(&dephased).unwrap_err();
+        assert!(err.contains("T2'"));
+
+        dephased.exchange.clear();
+        assert!(fid.simulate(&dephased).is_ok());
+    }
+
+    #[test]
+    fn test_dispatch_uses_tissue() {
+        let selection = SequenceSelection::SE(se::SeParams {
+            t1: 0.0,
+            t2: 0.0,
+            refocus_angle: PI,
+            refocus_phase: PI / 2.0,
+            echo_time: 0.05,
+            debug_print: false,
+        });
+        let signal = simulate(&selection, &tissue()).unwrap();
+        let expected = 0.8 * (-0.05_f64 / 0.1).exp();
+        assert!((signal[0].norm() - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_events_match_readouts() {
+        let params = fse::FseParams {
+            restore: true,
+            tr: 1.0,
+            nshots: 2,
+            ..fse::FseParams::new(8, 1.0, 0.1, 0.01, PI, PI / 2.0)
+        };
+        let times = params.readout_times();
+        assert_eq!(times.len(), 8);
+        assert!((times[0] - 1.01).abs() < 1e-12);
+        assert_eq!(params.simulate(&tissue()).unwrap().len(), 8);
+    }
+
+    /// Small instance of every selectable sequence.
+    fn every_sequence() -> Vec<SequenceSelection> {
+        use dephasing::Dephasing;
+        use mprage::ViewOrder;
+        use t2prep::{Readout, RefocusScheme, T2Prep};
+
+        let deg = |x: f64| x.to_radians();
+        let prep = T2Prep {
+            duration: 0.05,
+            nrefocus: 4,
+            scheme: RefocusScheme::Mlev,
+            b1: 1.0,
+        };
+        let diffusion = dwse::DiffusionGradient {
+            strength: 0.02,
+            duration: 0.01,
+            separation: 0.02,
+        };
+        vec![
+            SequenceSelection::FSE(fse::FseParams {
+                restore: true,
+                tr: 0.5,
+                nshots: 2,
+                ..fse::FseParams::new(8, 1.0, 0.1, 0.01, deg(150.0), 0.0)
+            }),
+            SequenceSelection::SE(se::SeParams {
+                t1: 1.0,
+                t2: 0.1,
+                refocus_angle: deg(160.0),
+                refocus_phase: PI / 2.0,
+                echo_time: 0.05,
+                debug_print: false,
+            }),
+            SequenceSelection::FID(fid::FidParams {
+                nreads: 4,
+                t1: 1.0,
+                t2: 0.1,
+                echo_time: 0.005,
+                dephasing: Dephasing::None,
+                debug_print: false,
+            }),
+            SequenceSelection::SPACE(space::SpaceParams {
+                restore: true,
+                tr: 0.5,
+                nshots: 2,
+                ..space::SpaceParams::new(8, 1.0, 0.1, 0.005, deg(120.0), 0.0)
+            }),
+            SequenceSelection::MPRAGE(mprage::MprageParams {
+                t1: 1.0,
+                t2: 0.1,
+                inversion_time: 0.5,
+                flip_angle: deg(8.0),
+                esp: 0.008,
+                nreads: 16,
+                recovery_time: 0.5,
+                nshots: 2,
+                view_order: ViewOrder::Linear,
+                debug_print: false,
+            }),
+            SequenceSelection::MP2RAGE(mp2rage::Mp2rageParams {
+                t1: 1.0,
+                t2: 0.1,
+                inversion_time_1: 0.7,
+                inversion_time_2: 2.2,
+                flip_angle_1: deg(4.0),
+                flip_angle_2: deg(5.0),
+                esp: 0.007,
+                nreads: 16,
+                mp2rage_tr: 5.0,
+                b1: 1.0,
+                nshots: 2,
+                view_order: ViewOrder::Centric,
+                debug_print: false,
+            }),
+            SequenceSelection::IRFSE(irfse::IrFseParams {
+                etl: 8,
+                t1: 1.0,
+                t2: 0.1,
+                esp: 0.01,
+                refocus_angle: deg(150.0),
+                cpmg_phase: 0.0,
+                inversion: irfse::Inversion::Ideal,
+                inversion_time: 0.5,
+                tr: 2.0,
+                nshots: 2,
+                debug_print: false,
+            }),
+            SequenceSelection::STEAM(steam::SteamParams {
+                t1: 1.0,
+                t2: 0.1,
+                flip_angles: [deg(80.0), deg(90.0), deg(100.0)],
+                phases: [PI / 2.0; 3],
+                echo_time: 0.02,
+                mixing_time: 0.1,
+                crusher_twists: 2,
+                mixing_twists: 5,
+                debug_print: false,
+            }),
+            SequenceSelection::MRF(mrf::MrfParams {
+                t1: 1.0,
+                t2: 0.1,
+                flip_angles: (0..20).map(|ix| deg(10.0 + 3.0 * ix as f64)).collect(),
+                phases: vec![0.0; 20],
+                tr: vec![0.012; 20],
+                te: vec![0.002; 20],
+                dephasing: Dephasing::None,
+                inversion_time: Some(0.02),
+                n_states: 20,
+                debug_print: false,
+            }),
+            SequenceSelection::GRASE(grase::GraseParams {
+                etl: 4,
+                t1: 1.0,
+                t2: 0.1,
+                dephasing: Dephasing::None,
+                esp: 0.02,
+                refocus_angle: PI,
+                cpmg_phase: PI / 2.0,
+                ngre: 3,
+                gre_spacing: 0.002,
+                off_resonance: 0.0,
+                debug_print: false,
+            }),
+            SequenceSelection::LookLocker(looklocker::LookLockerParams {
+                t1: 1.0,
+                t2: 0.1,
+                flip_angle: deg(5.0),
+                tr: 0.05,
+                heart_rate: 60.0,
+                nbeats: 2,
+                debug_print: false,
+            }),
+            SequenceSelection::MOLLI(molli::MolliParams::scheme_5_3_3(1.0, 0.1, 60.0)),
+            SequenceSelection::AFI(afi::AfiParams {
+                t1: 1.0,
+                t2: 0.1,
+                flip_angle: deg(60.0),
+                b1: 1.0,
+                tr1: 0.02,
+                tr2: 0.1,
+                spoiling: Spoiling::Ideal,
+                tr2_twists: 5,
+                npairs: 10,
+                n_states: 20,
+                debug_print: false,
+            }),
+            SequenceSelection::DAM(dam::DamParams {
+                t1: 1.0,
+                t2: 0.1,
+                flip_angle: deg(60.0),
+                b1: 1.0,
+                tr: 0.05,
+                spoiling: Spoiling::Gradient {
+                    rf_increment: deg(117.0),
+                    twists: 1,
+                },
+                npulses: 10,
+                n_states: 20,
+                debug_print: false,
+            }),
+            SequenceSelection::DESPOT1(despot1::Despot1Params {
+                t1: 1.0,
+                t2: 0.1,
+                flip_angles: vec![deg(3.0), deg(17.0)],
+                tr: 0.01,
+                b1: 1.0,
+                spoiling: Spoiling::Ideal,
+                npulses: 20,
+                n_states: 20,
+                debug_print: false,
+            }),
+            SequenceSelection::DESPOT2(despot2::Despot2Params {
+                t1: 1.0,
+                t2: 0.1,
+                flip_angles: vec![deg(15.0), deg(60.0)],
+                tr: 0.005,
+                b1: 1.0,
+                t1_estimate: 1.0,
+                npulses: 20,
+                debug_print: false,
+            }),
+            SequenceSelection::T2Prep(t2prep::T2PrepParams {
+                t1: 1.0,
+                t2: 0.1,
+                prep,
+                delay: 0.01,
+                readout: Readout::BalancedSsfp {
+                    flip_angle: deg(40.0),
+                    tr: 0.004,
+                    nreads: 8,
+                },
+                debug_print: false,
+            }),
+            SequenceSelection::FatSat(fat::FatSatParams {
+                water_t1: 1.0,
+                water_t2: 0.1,
+                water_t2s: 0.05,
+                fat: fat::FatModel {
+                    spectrum: fat::FatSpectrum::hamilton(),
+                    t1: 0.38,
+                    t2: 0.08,
+                    t2s: 0.01,
+                    fraction: 0.2,
+                },
+                b0: 3.0,
+                suppression: fat::FatSuppression::Stir {
+                    inversion_time: 0.2,
+                },
+                protocol: fat::FatProtocol::Spgr {
+                    flip_angle: deg(15.0),
+                    tr: 0.01,
+                    te: 0.004,
+                    nreads: 8,
+                },
+                nshots: 2,
+                debug_print: false,
+            }),
+            SequenceSelection::QALAS(qalas::QalasParams {
+                t1: 1.0,
+                t2: 0.1,
+                t2prep: prep,
+                readout: Readout::SpoiledGre {
+                    flip_angle: deg(4.0),
+                    tr: 0.0023,
+                    nreads: 8,
+                },
+                inversion_delay: 0.01,
+                inversion_time: 0.1,
+                block_spacing: 0.9,
+                tr: 4.5,
+                nshots: 2,
+                debug_print: false,
+            }),
+            SequenceSelection::DWSE(dwse::DwSeParams {
+                t1: 1.0,
+                t2: 0.1,
+                adc: 1e-9,
+                echo_time: 0.05,
+                gradient: diffusion,
+                debug_print: false,
+            }),
+            SequenceSelection::DWSTEAM(dwsteam::DwSteamParams {
+                t1: 1.0,
+                t2: 0.1,
+                adc: 1e-9,
+                echo_time: 0.03,
+                mixing_time: 0.1,
+                gradient: dwse::DiffusionGradient {
+                    separation: 0.12,
+                    ..diffusion
+                },
+                mixing_twists: 3,
+                debug_print: false,
+            }),
+            SequenceSelection::GRE(gre::GreParams {
+                t1: 1.0,
+                t2: 0.1,
+                flip_angle: deg(20.0),
+                tr: 0.02,
+                echo_time: 0.01,
+                nreads: 10,
+                spoiling: Spoiling::Gradient {
+                    rf_increment: deg(117.0),
+                    twists: 1,
+                },
+                dephasing: Dephasing::None,
+                debug_print: false,
+            }),
+            SequenceSelection::DCE(dce::DceParams {
+                agent: crate::tissues::agent::Agent::gadobutrol(
+                    crate::tissues::FieldStrength::T3,
+                ),
+                model: crate::tissues::agent::Tofts {
+                    ktrans: 0.2 / 60.0,
+                    ve: 0.3,
+                    vp: 0.05,
+                },
+                aif: crate::tissues::agent::ArterialInput::Parker {
+                    onset: 1.0,
+                    hematocrit: 0.42,
+                },
+                flip_angle: deg(15.0),
+                tr: 0.004,
+                nreads: 50,
+                nframes: 4,
+                spoiling: Spoiling::Ideal,
+                debug_print: false,
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_every_sequence_events_match_signal() {
+        // no diffusion, so the diffusion weighted sequences accept exchange
+        let still = |t: TissueProperties| TissueProperties {
+            compartments: t
+                .compartments
+                .into_iter()
+                .map(|c| Compartment { adc: 0.0, ..c })
+                .collect(),
+            ..t
+        };
+        let coupled = still(myelinated(0.0));
+        let independent = TissueProperties {
+            exchange: vec![],
+            ..coupled.clone()
+        };
+
+        for selection in every_sequence() {
+            let sequence = selection.as_sequence();
+            let name = sequence.name();
+            let signal = simulate(&selection, &independent).unwrap();
+            assert!(!signal.is_empty(), "{}", name);
+            assert_eq!(sequence.readout_times().len(), signal.len(), "{}", name);
+
+            let events = sequence.events();
+            assert!(events.windows(2).all(|e| e[0].time() <= e[1].time()), "{}", name);
+            assert!(events.last().unwrap().time() <= sequence.duration() + 1e-9, "{}", name);
+
+            match sequence.simulate(&coupled) {
+                Ok(exchanged) => {
+                    for (c, i) in exchanged.iter().zip(signal.iter()) {
+                        assert!((c - i).norm() < 1e-6, "{}: {} vs {}", name, c, i);
+                    }
+                }
+                Err(_) => assert!(matches!(name, "GRASE" | "DCE" | "FatSat"), "{}", name),
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_sequence_takes_builtin_tissues() {
+        for selection in every_sequence() {
+            let sequence = selection.as_sequence();
+            for tissue in Tissue::ALL {
+                let tissue = get_tissue(tissue, FieldStrength::T3);
+                let signal = simulate(&selection, &tissue).unwrap();
+                let expected = sequence.signal(&tissue.pools()[0]);
+                assert_eq!(signal.len(), expected.len(), "{}", sequence.name());
+                for (s, e) in signal.iter().zip(expected.iter()) {
+                    assert!((s - tissue.pd * e).norm() < 1e-12, "{}", sequence.name());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_validation_rejects_short_tr() {
+        let selection = SequenceSelection::SPACE(space::SpaceParams {
+            tr: 0.5,
+            ..space::SpaceParams::new(100, 1.0, 0.1, 0.01, PI, PI / 2.0)
+        });
+        assert!(simulate(&selection, &tissue()).is_err());
+    }
+}
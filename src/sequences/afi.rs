@@ -0,0 +1,199 @@
+use num_complex::Complex64;
+
+use super::common::{relaxation_factors, spoil, spoiled_pulse, RfSpoiler, Spoiling, Timeline};
+use super::{Event, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, EPG},
+};
+
+#[derive(Clone)]
+pub struct AfiParams {
+    pub t1: f64,
+    pub t2: f64,
+    /// Nominal flip angle.
+    pub flip_angle: f64,
+    /// Relative transmit field, scales the nominal flip angle.
+    pub b1: f64,
+    pub tr1: f64,
+    /// Second (longer) repetition time.
+    pub tr2: f64,
+    /// Spoiling after each readout. Gradient spoiling plays its twists in TR1.
+    pub spoiling: Spoiling,
+    /// Spoiler twists played in TR2 under gradient spoiling, usually the TR1 twists
+    /// scaled by TR2 / TR1.
+    pub tr2_twists: i32,
+    /// Number of TR1/TR2 pairs simulated. The last pair is reported.
+    pub npairs: usize,
+    /// Number of EPG states retained for gradient spoiling.
+    pub n_states: usize,
+    pub debug_print: bool,
+}
+
+pub struct AfiSignal {
+    /// Signal read in the TR1 interval.
+    pub s1: Complex64,
+    /// Signal read in the TR2 interval.
+    pub s2: Complex64,
+    /// Flip angle estimated from the signal ratio.
+    pub flip_angle_estimate: f64,
+}
+
+/// AFI flip angle estimate, `arccos((r n - 1) / (n - r))` with `r = S2 / S1` and
+/// `n = TR2 / TR1`.
+pub fn estimate_flip_angle(s1: f64, s2: f64, tr1: f64, tr2: f64) -> f64 {
+    let r = s2 / s1;
+    let n = tr2 / tr1;
+    ((r * n - 1.0) / (n - r)).clamp(-1.0, 1.0).acos()
+}
+
+pub fn simulate(params: AfiParams) -> AfiSignal {
+    assert!(params.tr2 > params.tr1, "TR2 must be longer than TR1");
+    assert!(params.npairs > 0, "at least one TR pair must be simulated");
+
+    let flip_angle = params.b1 * params.flip_angle;
+    let mut epg = epg::vec::EPGVecRepresentation::new(params.n_states);
+    let (mut s1, mut s2) = (Complex64::from(0.0), Complex64::from(0.0));
+
+    let (et1_1, et2_1) = relaxation_factors(params.tr1, params.t1, params.t2);
+    let (et1_2, et2_2) = relaxation_factors(params.tr2, params.t1, params.t2);
+
+    let mut spoiler = RfSpoiler::for_spoiling(params.spoiling);
+
+    for _ in 0..params.npairs {
+        s1 = spoiled_pulse(&mut epg, flip_angle, &mut spoiler);
+        spoil(&mut epg, params.spoiling);
+        epg.delay(et1_1, et2_1);
+
+        s2 = spoiled_pulse(&mut epg, flip_angle, &mut spoiler);
+        spoil(&mut epg, params.tr2_spoiling());
+        epg.delay(et1_2, et2_2);
+    }
+
+    let flip_angle_estimate = estimate_flip_angle(s1.re, s2.re, params.tr1, params.tr2);
+
+    if params.debug_print {
+        println!("S1 {:?} S2 {:?} flip estimate {:.4}", s1, s2, flip_angle_estimate);
+        println!("{:}", epg::vec::to_mxy(&epg));
+        println!("{:}", epg::vec::to_mz(&epg));
+    }
+
+    AfiSignal {
+        s1,
+        s2,
+        flip_angle_estimate,
+    }
+}
+
+impl AfiParams {
+    /// Spoiling of the TR2 interval.
+    fn tr2_spoiling(&self) -> Spoiling {
+        match self.spoiling {
+            Spoiling::Ideal => Spoiling::Ideal,
+            Spoiling::Gradient { rf_increment, .. } => Spoiling::Gradient {
+                rf_increment,
+                twists: self.tr2_twists,
+            },
+        }
+    }
+}
+
+/// Error of the estimated flip angle against the true `b1 * flip_angle`, for each T1.
+pub fn t1_bias(params: &AfiParams, t1_values: &[f64]) -> Vec<f64> {
+    t1_values
+        .iter()
+        .map(|&t1| {
+            let mut p = params.clone();
+            p.t1 = t1;
+            p.debug_print = false;
+            simulate(p).flip_angle_estimate - params.b1 * params.flip_angle
+        })
+        .collect()
+}
+
+/// Reports S1 and S2 of the last pair.
+impl Sequence for AfiParams {
+    fn name(&self) -> &'static str {
+        "AFI"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.tr1 <= 0.0 || self.tr2 <= self.tr1 {
+            return Err("TR2 must be longer than a positive TR1".into());
+        }
+        if self.npairs == 0 {
+            return Err("at least one TR pair must be simulated".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let flip_angle = self.b1 * self.flip_angle;
+        let mut timeline = Timeline::new();
+        let mut spoiler = RfSpoiler::for_spoiling(self.spoiling);
+        for pair in 0..self.npairs {
+            timeline.record = pair + 1 == self.npairs;
+            let intervals = [(self.spoiling, self.tr1), (self.tr2_spoiling(), self.tr2)];
+            for (spoiling, tr) in intervals {
+                timeline.spoiled_pulse(flip_angle, &mut spoiler);
+                timeline.spoil(spoiling);
+                timeline.delay(tr);
+            }
+        }
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        self.npairs as f64 * (self.tr1 + self.tr2)
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        let res = simulate(AfiParams {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.clone()
+        });
+        vec![res.s1, res.s2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(spoiling: Spoiling) -> AfiParams {
+        AfiParams {
+            t1: 1.0,
+            t2: 0.05,
+            flip_angle: 60.0_f64.to_radians(),
+            b1: 0.9,
+            tr1: 0.02,
+            tr2: 0.1,
+            spoiling,
+            tr2_twists: 5,
+            npairs: 100,
+            n_states: 60,
+            debug_print: false,
+        }
+    }
+
+    #[test]
+    fn test_ideal_spoiling_estimate() {
+        let bias = t1_bias(&params(Spoiling::Ideal), &[0.5, 1.0, 2.0]);
+        assert!(bias.iter().all(|b| b.abs() < 1.5_f64.to_radians()));
+    }
+
+    #[test]
+    fn test_incomplete_spoiling_bias() {
+        let ideal = simulate(params(Spoiling::Ideal));
+        let poor = simulate(params(Spoiling::Gradient {
+            rf_increment: 0.0,
+            twists: 1,
+        }));
+        let expected = 54.0_f64.to_radians();
+        assert!(
+            (poor.flip_angle_estimate - expected).abs()
+                > (ideal.flip_angle_estimate - expected).abs()
+        );
+    }
+}
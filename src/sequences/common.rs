@@ -0,0 +1,681 @@
+use nalgebra::DMatrix;
+use num_complex::{Complex, Complex64};
+
+use std::f64::consts::PI;
+use std::ops::Range;
+
+use super::dephasing::Dephasing;
+use super::Event;
+use crate::epg::exchange::EPGExchange;
+use crate::{
+    epg,
+    types::{Compartment, Exchange, TissueProperties, EPG},
+};
+
+/// Phase increment commonly used for quadratic RF spoiling (117 degrees).
+pub(crate) const RF_SPOIL_INCREMENT: f64 = 117.0 * PI / 180.0;
+
+/// Transverse spoiling between readouts of a spoiled sequence.
+#[derive(Clone, Copy)]
+pub enum Spoiling {
+    /// All transverse magnetization is destroyed after each readout.
+    Ideal,
+    /// RF phase cycling plus spoiler twists per TR.
+    Gradient { rf_increment: f64, twists: i32 },
+}
+
+/// Relaxation factors (E1, E2) over an interval `dt`.
+pub(crate) fn relaxation_factors(dt: f64, t1: f64, t2: f64) -> (Complex64, Complex64) {
+    let et1d = Complex64::from((-dt / t1).exp());
+    let et2d = Complex64::from((-dt / t2).exp());
+    (et1d, et2d)
+}
+
+/// Quadratic RF spoiling phase cycle. Each call to `next_phase` returns the
+/// transmit (and receive) phase offset of the next pulse.
+pub(crate) struct RfSpoiler {
+    increment: f64,
+    step: f64,
+    phase: f64,
+}
+
+impl RfSpoiler {
+    pub(crate) fn new(increment: f64) -> Self {
+        Self {
+            increment,
+            step: 0.0,
+            phase: 0.0,
+        }
+    }
+
+    /// Phase cycle matching a spoiling scheme. Ideal spoiling needs no cycling.
+    pub(crate) fn for_spoiling(spoiling: Spoiling) -> Self {
+        match spoiling {
+            Spoiling::Ideal => Self::new(0.0),
+            Spoiling::Gradient { rf_increment, .. } => Self::new(rf_increment),
+        }
+    }
+
+    pub(crate) fn next_phase(&mut self) -> f64 {
+        let phase = self.phase;
+        self.step = (self.step + self.increment) % (2.0 * PI);
+        self.phase = (self.phase + self.step) % (2.0 * PI);
+        phase
+    }
+}
+
+/// Ideal inversion pulse followed by a spoiler gradient.
+pub(crate) fn invert<E: EPG>(epg: &mut E) {
+    let x180 = epg::common::gen_rotation_matrix(PI, 0.0);
+    epg.rotate(&x180);
+    epg.spoil(1);
+}
+
+/// Play the next pulse of an RF-spoiled train and read the demodulated signal
+/// directly after it.
+pub(crate) fn spoiled_pulse<E: EPG>(epg: &mut E, flip_angle: f64, spoiler: &mut RfSpoiler) -> Complex64 {
+    let phase = spoiler.next_phase();
+    let rf = epg::common::gen_rotation_matrix(flip_angle, PI / 2.0 + phase);
+    epg.rotate(&rf);
+    epg.read() * (-Complex::i() * phase).exp()
+}
+
+/// Train of RF-spoiled gradient echoes spaced by `esp`. Each echo is read
+/// directly after its pulse and demodulated with the receiver phase, so an
+/// unspoiled excitation of fully relaxed magnetization reads as `sin(flip_angle)`.
+pub(crate) fn spoiled_gre_train<E: EPG>(
+    epg: &mut E,
+    flip_angle: f64,
+    esp: f64,
+    nreads: usize,
+    t1: f64,
+    t2: f64,
+    spoiler: &mut RfSpoiler,
+) -> Vec<Complex64> {
+    let mut signal: Vec<Complex64> = Vec::with_capacity(nreads);
+    let (et1d, et2d) = relaxation_factors(esp, t1, t2);
+
+    for _ in 0..nreads {
+        signal.push(spoiled_pulse(epg, flip_angle, spoiler));
+        epg.grelax(et1d, et2d, 1);
+    }
+
+    signal
+}
+
+/// Balanced SSFP train with an alpha/2 - TR/2 catalyzation and alternating pulse
+/// phase. Echoes are read at TE = TR/2, demodulated with the receiver alternation.
+pub(crate) fn balanced_ssfp_train<E: EPG>(
+    epg: &mut E,
+    flip_angle: f64,
+    tr: f64,
+    nreads: usize,
+    t1: f64,
+    t2: f64,
+) -> Vec<Complex64> {
+    let mut signal: Vec<Complex64> = Vec::with_capacity(nreads);
+    let (et1d, et2d) = relaxation_factors(tr / 2.0, t1, t2);
+
+    let prep = epg::common::gen_rotation_matrix(flip_angle / 2.0, -PI / 2.0);
+    let rf_pos = epg::common::gen_rotation_matrix(flip_angle, PI / 2.0);
+    let rf_neg = epg::common::gen_rotation_matrix(flip_angle, -PI / 2.0);
+
+    epg.rotate(&prep);
+    epg.delay(et1d, et2d);
+
+    for ix in 0..nreads {
+        let sign = if ix % 2 == 0 { 1.0 } else { -1.0 };
+        epg.rotate(if ix % 2 == 0 { &rf_pos } else { &rf_neg });
+        epg.delay(et1d, et2d);
+        signal.push(epg.read() * sign);
+        epg.delay(et1d, et2d);
+    }
+
+    signal
+}
+
+/// Transverse spoiling after a readout.
+pub(crate) fn spoil<E: EPG>(epg: &mut E, spoiling: Spoiling) {
+    match spoiling {
+        Spoiling::Ideal => epg.crush(),
+        Spoiling::Gradient { twists, .. } => epg.spoil(twists),
+    }
+}
+
+/// Signal of the last of `npulses` spoiled gradient echo pulses, starting from equilibrium.
+pub(crate) fn spoiled_steady_state(
+    flip_angle: f64,
+    tr: f64,
+    t1: f64,
+    t2: f64,
+    spoiling: Spoiling,
+    npulses: usize,
+    n_states: usize,
+) -> Complex64 {
+    let mut epg = epg::vec::EPGVecRepresentation::new(n_states);
+    let mut spoiler = RfSpoiler::for_spoiling(spoiling);
+    let mut signal = Complex64::from(0.0);
+    let (et1d, et2d) = relaxation_factors(tr, t1, t2);
+
+    for _ in 0..npulses {
+        signal = spoiled_pulse(&mut epg, flip_angle, &mut spoiler);
+        spoil(&mut epg, spoiling);
+        epg.delay(et1d, et2d);
+    }
+
+    signal
+}
+
+/// Multi-shot CPMG echo train shared by FSE, SPACE and IR-FSE.
+#[derive(Clone)]
+pub(crate) struct EchoTrain {
+    pub etl: usize,
+    pub t1: f64,
+    pub t2: f64,
+    pub esp: f64,
+    pub refocus_angle: f64,
+    pub cpmg_phase: f64,
+    pub restore: bool,
+    pub tr: f64,
+    pub nshots: usize,
+    /// Pulses played at the start of each shot as (flip angle, phase), followed by a
+    /// crusher. Empty for no preparation.
+    pub prep: Vec<(f64, f64)>,
+    /// Time from the start of the shot to the excitation.
+    pub prep_time: f64,
+    /// Free precession in radians over each half echo spacing, for off-resonant species.
+    pub precession: f64,
+}
+
+/// Result of `EchoTrain::run`.
+pub(crate) struct TrainSignal {
+    /// Echoes of the last shot.
+    pub echoes: Vec<Complex64>,
+    /// Longitudinal magnetization just before the excitation of the last shot.
+    pub mz_excitation: f64,
+    /// Longitudinal magnetization available at the start of the shot after the last.
+    pub mz_next: f64,
+}
+
+impl EchoTrain {
+    /// Free recovery between the end of the echo train and the next shot.
+    pub(crate) fn recovery_time(&self) -> f64 {
+        self.tr - self.prep_time - self.etl as f64 * self.esp
+    }
+
+    pub(crate) fn run(&self, debug_print: bool) -> TrainSignal {
+        let etl = self.etl;
+        let recovery = self.recovery_time();
+
+        assert!(self.prep_time >= 0.0, "preparation time must be positive");
+        // allow for rounding when the train fills the whole TR
+        assert!(recovery > -1e-9, "TR too short for the echo train");
+        let recovery = recovery.max(0.0);
+        assert!(self.nshots > 0, "at least one shot must be simulated");
+
+        // every state reached within a shot, so none is truncated
+        let mut epg = epg::vec::EPGVecRepresentation::new(2 * etl + 1);
+
+        let mut signal: Vec<Complex64> = Vec::with_capacity(etl + 1);
+        let mut mz_excitation = 1.0;
+
+        let prep: Vec<_> = self
+            .prep
+            .iter()
+            .map(|&(alpha, phi)| epg::common::gen_rotation_matrix(alpha, phi))
+            .collect();
+        let x180 = epg::common::gen_rotation_matrix(self.refocus_angle, self.cpmg_phase);
+
+        // dt is the spacing of our events, unsed for dephasing/relaxation
+        let dt = self.esp / 2.0;
+        let (et1d, et2d) = relaxation_factors(dt, self.t1, self.t2);
+        let (et1_prep, et2_prep) = relaxation_factors(self.prep_time, self.t1, self.t2);
+        let (et1_tr, et2_tr) = relaxation_factors(recovery, self.t1, self.t2);
+
+        for _ in 0..self.nshots {
+            if !prep.is_empty() {
+                for rf in prep.iter() {
+                    epg.rotate(rf);
+                }
+                epg.crush();
+            }
+            epg.delay(et1_prep, et2_prep);
+            mz_excitation = epg.read_mz().re;
+
+            signal.clear();
+            epg.excite();
+
+            for _ in 0..etl {
+                epg.grelax(et1d, et2d, 1);
+                epg.precess(self.precession);
+                epg.rotate(&x180);
+                epg.grelax(et1d, et2d, 1);
+                epg.precess(self.precession);
+
+                signal.push(epg.read());
+            }
+
+            if self.restore {
+                // tip the last echo back along +z, the inverse of excite() for its phase.
+                let echo_phase = epg.read().arg();
+                let restore = epg::common::gen_rotation_matrix(-PI / 2.0, PI / 2.0 + echo_phase);
+                epg.rotate(&restore);
+            }
+
+            epg.crush();
+            epg.delay(et1_tr, et2_tr);
+        }
+
+        if debug_print {
+            println!("Signal: {:?}", signal);
+            println!("{:}", epg::vec::to_mxy(&epg));
+            println!("{:}", epg::vec::to_mz(&epg));
+        }
+
+        TrainSignal {
+            echoes: signal,
+            mz_excitation,
+            mz_next: epg.read_mz().re,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.etl == 0 {
+            return Err("echo train needs at least one echo".into());
+        }
+        if self.esp <= 0.0 {
+            return Err("echo spacing must be positive".into());
+        }
+        if self.prep_time < 0.0 {
+            return Err("preparation time must be positive".into());
+        }
+        if self.nshots == 0 {
+            return Err("at least one shot must be simulated".into());
+        }
+        if self.recovery_time() <= -1e-9 {
+            return Err("TR too short for the preparation and echo train".into());
+        }
+        Ok(())
+    }
+
+    /// Events of every shot, with the readouts of the last.
+    pub(crate) fn timeline(&self) -> Timeline {
+        let mut timeline = Timeline::new();
+        for shot in 0..self.nshots {
+            let start = timeline.now();
+            timeline.record = shot + 1 == self.nshots;
+            if !self.prep.is_empty() {
+                for &(flip_angle, phase) in self.prep.iter() {
+                    timeline.pulse(flip_angle, phase);
+                }
+                timeline.crush();
+            }
+            timeline.delay(self.prep_time);
+            timeline.excite();
+            for _ in 0..self.etl {
+                timeline.gradient(self.esp / 2.0, 1);
+                timeline.pulse(self.refocus_angle, self.cpmg_phase);
+                timeline.gradient(self.esp / 2.0, 1);
+                timeline.readout(0.0);
+            }
+            if self.restore {
+                timeline.restore();
+            }
+            timeline.crush();
+            timeline.delay((start + self.tr - timeline.now()).max(0.0));
+        }
+        timeline
+    }
+}
+
+/// Least squares line through (x, y), returned as (slope, intercept).
+pub(crate) fn linear_fit(x: &[f64], y: &[f64]) -> (f64, f64) {
+    let n = x.len() as f64;
+    let mx = x.iter().sum::<f64>() / n;
+    let my = y.iter().sum::<f64>() / n;
+    let sxy = x.iter().zip(y).map(|(a, b)| (a - mx) * (b - my)).sum::<f64>();
+    let sxx = x.iter().map(|a| (a - mx).powi(2)).sum::<f64>();
+    let slope = sxy / sxx;
+    (slope, my - slope * mx)
+}
+
+/// Nelder-Mead simplex minimisation of `f` from `x0`, with initial simplex steps `step`.
+/// Returns the best vertex after `iterations` steps.
+pub(crate) fn nelder_mead<F: Fn(&[f64]) -> f64>(
+    f: F,
+    x0: &[f64],
+    step: &[f64],
+    iterations: usize,
+) -> Vec<f64> {
+    let n = x0.len();
+    let mut simplex: Vec<(Vec<f64>, f64)> = (0..=n)
+        .map(|ix| {
+            let mut x = x0.to_vec();
+            if ix > 0 {
+                x[ix - 1] += step[ix - 1];
+            }
+            let fx = f(&x);
+            (x, fx)
+        })
+        .collect();
+
+    let along = |a: &[f64], b: &[f64], t: f64| -> Vec<f64> {
+        a.iter().zip(b).map(|(a, b)| a + t * (b - a)).collect()
+    };
+
+    for _ in 0..iterations {
+        simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let centroid: Vec<f64> = (0..n)
+            .map(|d| simplex[..n].iter().map(|v| v.0[d]).sum::<f64>() / n as f64)
+            .collect();
+        let worst = simplex[n].clone();
+
+        let reflected = along(&centroid, &worst.0, -1.0);
+        let f_reflected = f(&reflected);
+
+        if f_reflected < simplex[0].1 {
+            let expanded = along(&centroid, &worst.0, -2.0);
+            let f_expanded = f(&expanded);
+            simplex[n] = if f_expanded < f_reflected {
+                (expanded, f_expanded)
+            } else {
+                (reflected, f_reflected)
+            };
+        } else if f_reflected < simplex[n - 1].1 {
+            simplex[n] = (reflected, f_reflected);
+        } else {
+            let contracted = along(&centroid, &worst.0, 0.5);
+            let f_contracted = f(&contracted);
+            if f_contracted < worst.1 {
+                simplex[n] = (contracted, f_contracted);
+            } else {
+                let best = simplex[0].0.clone();
+                for vertex in simplex.iter_mut().skip(1) {
+                    let x = along(&best, &vertex.0, 0.5);
+                    let fx = f(&x);
+                    *vertex = (x, fx);
+                }
+            }
+        }
+    }
+
+    simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+    simplex.swap_remove(0).0
+}
+
+/// Interval of length `dt` with diffusion and relaxation, during which a gradient
+/// of `ntwists` twists of wavenumber `dk` (rad/m) is played.
+pub(crate) fn diffusion_interval<E: EPG>(
+    epg: &mut E,
+    dt: f64,
+    t1: f64,
+    t2: f64,
+    adc: f64,
+    dk: f64,
+    ntwists: i32,
+) {
+    let (et1d, et2d) = relaxation_factors(dt, t1, t2);
+    epg.diffuse(adc, dt, dk, ntwists);
+    epg.grelax(et1d, et2d, ntwists);
+}
+
+/// Events built alongside a simulation, one call per EPG operation of the sequence.
+pub(crate) struct Timeline {
+    now: f64,
+    events: Vec<Event>,
+    /// Readouts are only kept while set, e.g. for the reported shot.
+    pub(crate) record: bool,
+}
+
+impl Timeline {
+    pub(crate) fn new() -> Self {
+        Self {
+            now: 0.0,
+            events: Vec::new(),
+            record: true,
+        }
+    }
+
+    pub(crate) fn now(&self) -> f64 {
+        self.now
+    }
+
+    pub(crate) fn pulse(&mut self, flip_angle: f64, phase: f64) {
+        self.events.push(Event::Pulse {
+            time: self.now,
+            flip_angle,
+            phase,
+        });
+    }
+
+    /// 90 degree excitation about y, as `EPG::excite`.
+    pub(crate) fn excite(&mut self) {
+        self.pulse(PI / 2.0, PI / 2.0);
+    }
+
+    /// Ideal inversion and spoiler, as `invert`.
+    pub(crate) fn invert(&mut self) {
+        self.pulse(PI, 0.0);
+        self.gradient(0.0, 1);
+    }
+
+    pub(crate) fn gradient(&mut self, duration: f64, ntwists: i32) {
+        self.events.push(Event::Gradient {
+            time: self.now,
+            duration,
+            ntwists,
+        });
+        self.now += duration;
+    }
+
+    /// Free relaxation without gradients.
+    pub(crate) fn delay(&mut self, dt: f64) {
+        self.now += dt;
+    }
+
+    pub(crate) fn crush(&mut self) {
+        self.events.push(Event::Crusher { time: self.now });
+    }
+
+    pub(crate) fn restore(&mut self) {
+        self.events.push(Event::Restore { time: self.now });
+    }
+
+    /// Start an independent acquisition from equilibrium.
+    pub(crate) fn reset(&mut self) {
+        self.events.push(Event::Reset { time: self.now });
+    }
+
+    pub(crate) fn readout(&mut self, phase: f64) {
+        self.readout_at(0.0, phase);
+    }
+
+    /// Readout `offset` from now, for gradient echoes about a spin echo.
+    pub(crate) fn readout_at(&mut self, offset: f64, phase: f64) {
+        if self.record {
+            self.events.push(Event::Readout {
+                time: self.now + offset,
+                phase,
+                tau: offset.abs(),
+            });
+        }
+    }
+
+    /// Gradient echo read now, `tau` after the excitation.
+    pub(crate) fn gradient_echo(&mut self, tau: f64, phase: f64) {
+        if self.record {
+            self.events.push(Event::Readout {
+                time: self.now,
+                phase,
+                tau,
+            });
+        }
+    }
+
+    /// As `spoil`.
+    pub(crate) fn spoil(&mut self, spoiling: Spoiling) {
+        match spoiling {
+            Spoiling::Ideal => self.crush(),
+            Spoiling::Gradient { twists, .. } => self.gradient(0.0, twists),
+        }
+    }
+
+    /// As `spoiled_pulse`.
+    pub(crate) fn spoiled_pulse(&mut self, flip_angle: f64, spoiler: &mut RfSpoiler) {
+        let phase = spoiler.next_phase();
+        self.pulse(flip_angle, PI / 2.0 + phase);
+        self.readout(phase);
+    }
+
+    /// As `spoiled_gre_train`, keeping the readouts in `reads`.
+    pub(crate) fn spoiled_gre_train(
+        &mut self,
+        flip_angle: f64,
+        esp: f64,
+        nreads: usize,
+        spoiler: &mut RfSpoiler,
+        reads: Range<usize>,
+    ) {
+        let record = self.record;
+        for ix in 0..nreads {
+            self.record = record && reads.contains(&ix);
+            self.spoiled_pulse(flip_angle, spoiler);
+            self.gradient(esp, 1);
+        }
+        self.record = record;
+    }
+
+    /// As `balanced_ssfp_train`, keeping the readouts in `reads`.
+    pub(crate) fn balanced_ssfp_train(
+        &mut self,
+        flip_angle: f64,
+        tr: f64,
+        nreads: usize,
+        reads: Range<usize>,
+    ) {
+        self.pulse(flip_angle / 2.0, -PI / 2.0);
+        self.delay(tr / 2.0);
+
+        let record = self.record;
+        for ix in 0..nreads {
+            let (phase, receiver) = if ix % 2 == 0 { (PI / 2.0, 0.0) } else { (-PI / 2.0, PI) };
+            self.record = record && reads.contains(&ix);
+            self.pulse(flip_angle, phase);
+            self.delay(tr / 2.0);
+            self.readout(receiver);
+            self.delay(tr / 2.0);
+        }
+        self.record = record;
+    }
+
+    /// As `spoiled_steady_state`, keeping the last readout.
+    pub(crate) fn spoiled_steady_state(
+        &mut self,
+        flip_angle: f64,
+        tr: f64,
+        spoiling: Spoiling,
+        npulses: usize,
+    ) {
+        let mut spoiler = RfSpoiler::for_spoiling(spoiling);
+        let record = self.record;
+        for ix in 0..npulses {
+            self.record = record && ix + 1 == npulses;
+            self.spoiled_pulse(flip_angle, &mut spoiler);
+            self.spoil(spoiling);
+            self.delay(tr);
+        }
+        self.record = record;
+    }
+
+    /// Events in time order.
+    pub(crate) fn into_events(mut self) -> Vec<Event> {
+        self.events.sort_by(|a, b| a.time().total_cmp(&b.time()));
+        self.events
+    }
+}
+
+/// Play `events` on coupled phase graphs of the compartments of `tissue`, with
+/// exchange. Returns every readout with unit proton density.
+pub(crate) fn play_exchange(events: &[Event], tissue: &TissueProperties) -> Vec<Complex64> {
+    play_pools(events, &tissue.pools(), &tissue.exchange)
+}
+
+/// Play `events` on a single compartment with unit proton density, applying its T2'
+/// dephasing to gradient echoes.
+pub(crate) fn play_pool(events: &[Event], pool: &Compartment) -> Vec<Complex64> {
+    let pool = Compartment {
+        fraction: 1.0,
+        ..pool.clone()
+    };
+    play_pools(events, &[pool], &[])
+}
+
+fn play_pools(events: &[Event], pools: &[Compartment], exchange: &[Exchange]) -> Vec<Complex64> {
+    let n = pools.len();
+
+    let mut k = DMatrix::<f64>::zeros(n, n);
+    for ex in exchange.iter() {
+        let reverse = ex.rate * pools[ex.from].fraction / pools[ex.to].fraction;
+        k[(ex.to, ex.from)] += ex.rate;
+        k[(ex.from, ex.from)] -= ex.rate;
+        k[(ex.from, ex.to)] += reverse;
+        k[(ex.to, ex.to)] -= reverse;
+    }
+
+    // states needed between crushers
+    let mut n_states = 1;
+    let mut twists = 0;
+    for event in events.iter() {
+        match *event {
+            Event::Gradient { ntwists, .. } => twists += ntwists.unsigned_abs() as usize,
+            Event::Crusher { .. } | Event::Reset { .. } => twists = 0,
+            _ => (),
+        }
+        n_states = n_states.max(twists + 1);
+    }
+
+    let m0: Vec<f64> = pools.iter().map(|c| c.fraction).collect();
+    let t1: Vec<f64> = pools.iter().map(|c| c.t1).collect();
+    let t2: Vec<f64> = pools.iter().map(|c| c.t2).collect();
+    let equilibrium = || EPGExchange::new(n_states, &m0, &t1, &t2, k.clone());
+
+    // several dephased compartments are rejected by `Sequence::validate_events`
+    let dephasing = match pools {
+        [pool] => Dephasing::from_t2s(pool.t2, pool.t2s),
+        _ => Dephasing::None,
+    };
+    let mut epg = equilibrium();
+
+    let mut signal = Vec::new();
+    let mut now = 0.0;
+    for event in events.iter() {
+        if event.time() > now {
+            epg.grelax(event.time() - now, 0);
+            now = event.time();
+        }
+        match *event {
+            Event::Pulse {
+                flip_angle, phase, ..
+            } => epg.rotate(&epg::common::gen_rotation_matrix(flip_angle, phase)),
+            Event::Gradient {
+                duration, ntwists, ..
+            } => {
+                epg.grelax(duration, ntwists);
+                now += duration;
+            }
+            Event::Crusher { .. } => epg.crush(),
+            Event::Restore { .. } => {
+                let echo_phase = epg.read().arg();
+                epg.rotate(&epg::common::gen_rotation_matrix(-PI / 2.0, PI / 2.0 + echo_phase));
+            }
+            Event::Reset { .. } => epg = equilibrium(),
+            Event::Readout { phase, tau, .. } => {
+                let demodulation = (-Complex::i() * phase).exp();
+                signal.push(epg.read() * demodulation * dephasing.decay(tau));
+            }
+        }
+    }
+
+    signal
+}
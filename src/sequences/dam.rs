@@ -0,0 +1,186 @@
+use num_complex::Complex64;
+
+use super::common::{spoiled_steady_state, Spoiling, Timeline};
+use super::{Event, Sequence};
+use crate::types::Compartment;
+
+/// Double angle method: two spoiled acquisitions at alpha and 2 alpha.
+#[derive(Clone)]
+pub struct DamParams {
+    pub t1: f64,
+    pub t2: f64,
+    /// Nominal flip angle of the first acquisition, the second uses twice this.
+    pub flip_angle: f64,
+    /// Relative transmit field, scales the nominal flip angles.
+    pub b1: f64,
+    pub tr: f64,
+    pub spoiling: Spoiling,
+    /// Number of pulses simulated per acquisition. The last one is reported.
+    pub npulses: usize,
+    /// Number of EPG states retained for gradient spoiling.
+    pub n_states: usize,
+    pub debug_print: bool,
+}
+
+pub struct DamSignal {
+    /// Signal of the alpha acquisition.
+    pub s1: Complex64,
+    /// Signal of the 2 alpha acquisition.
+    pub s2: Complex64,
+    /// Flip angle estimated from the signal ratio.
+    pub flip_angle_estimate: f64,
+}
+
+/// Double angle estimate, `arccos(S2 / (2 S1))`. Exact for fully relaxed acquisitions.
+pub fn estimate_flip_angle(s1: f64, s2: f64) -> f64 {
+    (s2 / (2.0 * s1)).clamp(-1.0, 1.0).acos()
+}
+
+pub fn simulate(params: DamParams) -> DamSignal {
+    assert!(params.npulses > 0, "at least one pulse must be simulated");
+
+    let acquire = |flip_angle: f64| {
+        spoiled_steady_state(
+            flip_angle,
+            params.tr,
+            params.t1,
+            params.t2,
+            params.spoiling,
+            params.npulses,
+            params.n_states,
+        )
+    };
+    let s1 = acquire(params.b1 * params.flip_angle);
+    let s2 = acquire(2.0 * params.b1 * params.flip_angle);
+    let flip_angle_estimate = estimate_flip_angle(s1.re, s2.re);
+
+    if params.debug_print {
+        println!("S1 {:?} S2 {:?} flip estimate {:.4}", s1, s2, flip_angle_estimate);
+    }
+
+    DamSignal {
+        s1,
+        s2,
+        flip_angle_estimate,
+    }
+}
+
+/// Error of the estimated flip angle against the true `b1 * flip_angle`, for each T1.
+pub fn t1_bias(params: &DamParams, t1_values: &[f64]) -> Vec<f64> {
+    t1_values
+        .iter()
+        .map(|&t1| {
+            let mut p = params.clone();
+            p.t1 = t1;
+            p.debug_print = false;
+            simulate(p).flip_angle_estimate - params.b1 * params.flip_angle
+        })
+        .collect()
+}
+
+/// Error of the estimated flip angle against the true `b1 * flip_angle`, for each
+/// relative transmit field.
+pub fn b1_bias(params: &DamParams, b1_values: &[f64]) -> Vec<f64> {
+    b1_values
+        .iter()
+        .map(|&b1| {
+            let mut p = params.clone();
+            p.b1 = b1;
+            p.debug_print = false;
+            simulate(p).flip_angle_estimate - b1 * params.flip_angle
+        })
+        .collect()
+}
+
+/// Reports S1 and S2. The acquisitions are independent, each from equilibrium.
+impl Sequence for DamParams {
+    fn name(&self) -> &'static str {
+        "DAM"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.tr <= 0.0 {
+            return Err("TR must be positive".into());
+        }
+        if self.npulses == 0 {
+            return Err("at least one pulse must be simulated".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let mut timeline = Timeline::new();
+        let flip_angle = self.b1 * self.flip_angle;
+        timeline.spoiled_steady_state(flip_angle, self.tr, self.spoiling, self.npulses);
+        timeline.reset();
+        timeline.spoiled_steady_state(2.0 * flip_angle, self.tr, self.spoiling, self.npulses);
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        2.0 * self.npulses as f64 * self.tr
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        let res = simulate(DamParams {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.clone()
+        });
+        vec![res.s1, res.s2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(tr: f64, spoiling: Spoiling) -> DamParams {
+        DamParams {
+            t1: 1.0,
+            t2: 0.08,
+            flip_angle: 60.0_f64.to_radians(),
+            b1: 1.0,
+            tr,
+            spoiling,
+            npulses: 200,
+            n_states: 80,
+            debug_print: false,
+        }
+    }
+
+    #[test]
+    fn test_fully_relaxed_estimate_exact() {
+        let bias = b1_bias(&params(10.0, Spoiling::Ideal), &[0.7, 1.0, 1.3]);
+        assert!(bias.iter().all(|b| b.abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_b1_bias_with_short_tr() {
+        // ideally spoiled steady state, S ~ sin(a) (1 - E1) / (1 - E1 cos(a))
+        let p = params(0.5, Spoiling::Ideal);
+        let e1 = (-0.5_f64).exp();
+        let b1_values = [0.7, 1.0, 1.3];
+        let bias = b1_bias(&p, &b1_values);
+        for (&b1, b) in b1_values.iter().zip(bias) {
+            let a = b1 * p.flip_angle;
+            let ratio = a.cos() * (1.0 - e1 * a.cos()) / (1.0 - e1 * (2.0 * a).cos());
+            assert!((b - (ratio.acos() - a)).abs() < 1e-9);
+            assert!(b.abs() > 1.0_f64.to_radians());
+        }
+    }
+
+    #[test]
+    fn test_incomplete_spoiling_bias() {
+        // residual transverse magnetization survives a TR shorter than T2
+        let ideal = simulate(params(0.05, Spoiling::Ideal));
+        let poor = simulate(params(
+            0.05,
+            Spoiling::Gradient {
+                rf_increment: 0.0,
+                twists: 1,
+            },
+        ));
+        assert!((poor.flip_angle_estimate - ideal.flip_angle_estimate).abs() > 1e-2);
+    }
+}
@@ -0,0 +1,210 @@
+use num_complex::Complex64;
+
+use super::common::{relaxation_factors, spoil, spoiled_pulse, RfSpoiler, Spoiling, Timeline};
+use super::{Event, Sequence};
+use crate::tissues::agent::{Agent, ArterialInput, Tofts};
+use crate::types::{Compartment, TissueProperties};
+use crate::{epg, types::EPG};
+
+/// Dynamic contrast enhanced series of spoiled gradient echo frames. The tissue
+/// concentration follows the Tofts model and is held constant within each frame.
+#[derive(Clone)]
+pub struct DceParams {
+    pub agent: Agent,
+    pub model: Tofts,
+    pub aif: ArterialInput,
+    pub flip_angle: f64,
+    pub tr: f64,
+    /// Pulses per frame. The middle readout of each frame samples the k-space centre.
+    pub nreads: usize,
+    pub nframes: usize,
+    pub spoiling: Spoiling,
+    pub debug_print: bool,
+}
+
+pub struct DceSignal {
+    /// Time of each frame centre.
+    pub times: Vec<f64>,
+    /// Tissue concentration in mM during each frame.
+    pub concentration: Vec<f64>,
+    /// k-space centre signal of each frame, scaled by proton density.
+    pub signal: Vec<Complex64>,
+}
+
+impl DceParams {
+    pub fn frame_time(&self) -> f64 {
+        self.nreads as f64 * self.tr
+    }
+}
+
+/// Series of the pre-contrast `tissue`.
+pub fn simulate(params: DceParams, tissue: &TissueProperties) -> DceSignal {
+    assert!(params.nreads > 0, "at least one readout per frame");
+
+    let frame_time = params.frame_time();
+    let center = params.nreads / 2;
+    let times: Vec<f64> = (0..params.nframes)
+        .map(|ix| (ix as f64 + 0.5) * frame_time)
+        .collect();
+    let concentration = params.model.concentration(&params.aif, &times);
+
+    let n_states = match params.spoiling {
+        Spoiling::Ideal => 2,
+        Spoiling::Gradient { .. } => params.nreads + 1,
+    };
+    let mut epg = epg::vec::EPGVecRepresentation::new(n_states);
+    let mut spoiler = RfSpoiler::for_spoiling(params.spoiling);
+    let mut signal: Vec<Complex64> = Vec::with_capacity(params.nframes);
+
+    for &c in concentration.iter() {
+        let tissue = params.agent.enhance(tissue, c);
+        let (et1d, et2d) = relaxation_factors(params.tr, tissue.t1, tissue.t2);
+
+        for ix in 0..params.nreads {
+            let s = spoiled_pulse(&mut epg, params.flip_angle, &mut spoiler);
+            if ix == center {
+                signal.push(tissue.pd * s);
+            }
+            spoil(&mut epg, params.spoiling);
+            epg.delay(et1d, et2d);
+        }
+    }
+
+    if params.debug_print {
+        println!("Concentration: {:?}", concentration);
+        println!("Signal: {:?}", signal);
+    }
+
+    DceSignal {
+        times,
+        concentration,
+        signal,
+    }
+}
+
+/// Reports the k-space centre of each frame, with the simulated tissue as the
+/// pre-contrast tissue. Its relaxation changes from frame to frame, which the events
+/// do not describe, so it takes neither exchange nor a preparation.
+impl Sequence for DceParams {
+    fn name(&self) -> &'static str {
+        "DCE"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.nreads == 0 {
+            return Err("at least one readout per frame".into());
+        }
+        if self.tr <= 0.0 {
+            return Err("TR must be positive".into());
+        }
+        self.model.validate()?;
+        self.aif.validate()
+    }
+
+    fn validate_events(&self, tissue: &TissueProperties) -> Result<(), String> {
+        Err(format!("{}: DCE events do not describe the contrast uptake", tissue.name))
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let center = self.nreads / 2;
+        let mut timeline = Timeline::new();
+        let mut spoiler = RfSpoiler::for_spoiling(self.spoiling);
+        for _ in 0..self.nframes {
+            for ix in 0..self.nreads {
+                timeline.record = ix == center;
+                timeline.spoiled_pulse(self.flip_angle, &mut spoiler);
+                timeline.spoil(self.spoiling);
+                timeline.delay(self.tr);
+            }
+        }
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        self.nframes as f64 * self.frame_time()
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        let tissue = TissueProperties {
+            name: pool.name.clone(),
+            pd: 1.0,
+            t1: pool.t1,
+            t2: pool.t2,
+            t2s: pool.t2s,
+            adc: pool.adc,
+            compartments: vec![],
+            exchange: vec![],
+        };
+        simulate(self.clone(), &tissue).signal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tissues::{get_tissue, FieldStrength};
+    use crate::types::Tissue;
+
+    fn params() -> DceParams {
+        DceParams {
+            agent: Agent::gadobutrol(FieldStrength::T3),
+            model: Tofts {
+                ktrans: 0.2 / 60.0,
+                ve: 0.3,
+                vp: 0.05,
+            },
+            aif: ArterialInput::Parker {
+                onset: 20.0,
+                hematocrit: 0.42,
+            },
+            flip_angle: 15.0_f64.to_radians(),
+            tr: 0.004,
+            nreads: 500,
+            nframes: 40,
+            spoiling: Spoiling::Ideal,
+            debug_print: false,
+        }
+    }
+
+    #[test]
+    fn test_enhancement_follows_bolus() {
+        let tissue = get_tissue(Tissue::GreyMatter, FieldStrength::T3);
+        let res = simulate(params(), &tissue);
+
+        // baseline frames sit at the Ernst steady state
+        let (alpha, e1) = (15.0_f64.to_radians(), (-0.004 / tissue.t1).exp());
+        let ernst = tissue.pd * alpha.sin() * (1.0 - e1) / (1.0 - alpha.cos() * e1);
+        assert_eq!(res.concentration[5], 0.0);
+        assert!((res.signal[5].norm() - ernst).abs() < 1e-6);
+
+        let peak = res.signal.iter().map(|s| s.norm()).fold(0.0, f64::max);
+        assert!(peak > 2.0 * ernst);
+
+        // the trait simulates the tissue it is given
+        let signal = params().simulate(&tissue).unwrap();
+        assert!((signal[5] - res.signal[5]).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_rejects_invalid_model() {
+        let tissue = get_tissue(Tissue::GreyMatter, FieldStrength::T3);
+        assert!(params().validate().is_ok());
+
+        let mut no_ees = params();
+        no_ees.model.ve = 0.0;
+        let mut all_cells = params();
+        all_cells.aif = ArterialInput::Parker {
+            onset: 20.0,
+            hematocrit: 1.0,
+        };
+        let mut unpaired = params();
+        unpaired.aif = ArterialInput::Samples {
+            times: vec![0.0, 1.0],
+            concentrations: vec![1.0],
+        };
+        for params in [no_ees, all_cells, unpaired] {
+            assert!(params.validate().is_err());
+            assert!(params.simulate(&tissue).is_err());
+        }
+    }
+}
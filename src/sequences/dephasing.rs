@@ -0,0 +1,57 @@
+use std::f64::consts::PI;
+
+/// Reversible intravoxel dephasing (T2') from a static distribution of frequencies.
+/// Refocused at spin echoes, but not at gradient echoes.
+#[derive(Clone)]
+pub enum Dephasing {
+    None,
+    /// Lorentzian line of half-width `1 / (2 pi T2')`. The signal decays as
+    /// `exp(-|tau| / T2')`, `tau` being the time from the last spin echo or excitation.
+    Lorentzian { t2_prime: f64 },
+    /// Discrete isochromats in Hz relative to the voxel centre, each simulated with its own
+    /// precession. Weights sum to one.
+    Isochromats {
+        frequencies: Vec<f64>,
+        weights: Vec<f64>,
+    },
+}
+
+impl Dephasing {
+    /// Lorentzian dephasing giving an apparent decay of `t2s` with transverse relaxation `t2`.
+    /// T2* cannot exceed T2, a `t2s` at or above `t2` is clamped to no dephasing.
+    pub fn from_t2s(t2: f64, t2s: f64) -> Self {
+        if t2s >= t2 {
+            return Dephasing::None;
+        }
+        Dephasing::Lorentzian {
+            t2_prime: 1.0 / (1.0 / t2s - 1.0 / t2),
+        }
+    }
+
+    /// Analytic attenuation `tau` seconds away from the last refocusing.
+    pub(crate) fn decay(&self, tau: f64) -> f64 {
+        match *self {
+            Dephasing::Lorentzian { t2_prime } => (-tau.abs() / t2_prime).exp(),
+            _ => 1.0,
+        }
+    }
+
+    /// Angular frequency and weight of each isochromat to simulate. The analytic forms
+    /// use a single on-resonance isochromat.
+    pub(crate) fn isochromats(&self) -> Vec<(f64, f64)> {
+        match self {
+            Dephasing::Isochromats {
+                frequencies,
+                weights,
+            } => {
+                assert_eq!(frequencies.len(), weights.len(), "one weight per isochromat");
+                frequencies
+                    .iter()
+                    .zip(weights)
+                    .map(|(f, &w)| (2.0 * PI * f, w))
+                    .collect()
+            }
+            _ => vec![(0.0, 1.0)],
+        }
+    }
+}
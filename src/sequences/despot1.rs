@@ -0,0 +1,162 @@
+use num_complex::Complex64;
+
+use super::common::{linear_fit, spoiled_steady_state, Spoiling, Timeline};
+use super::{Event, Sequence};
+use crate::types::Compartment;
+
+/// Variable flip angle spoiled gradient echo protocol.
+#[derive(Clone)]
+pub struct Despot1Params {
+    pub t1: f64,
+    pub t2: f64,
+    /// Nominal flip angles of the protocol.
+    pub flip_angles: Vec<f64>,
+    pub tr: f64,
+    /// Relative transmit field. The estimator assumes the nominal flip angles.
+    pub b1: f64,
+    pub spoiling: Spoiling,
+    /// Number of pulses simulated per flip angle. The last one is reported.
+    pub npulses: usize,
+    /// Number of EPG states retained for gradient spoiling.
+    pub n_states: usize,
+    pub debug_print: bool,
+}
+
+pub struct Despot1Signal {
+    /// Steady state signal for each flip angle.
+    pub signal: Vec<Complex64>,
+    pub t1_estimate: f64,
+    pub m0_estimate: f64,
+}
+
+/// Linear DESPOT1 fit of `S / sin(a)` against `S / tan(a)`. The slope is E1 and the
+/// intercept `M0 (1 - E1)`. Returns (T1, M0).
+pub fn estimate(flip_angles: &[f64], signal: &[f64], tr: f64) -> (f64, f64) {
+    let x: Vec<f64> = flip_angles.iter().zip(signal).map(|(a, s)| s / a.tan()).collect();
+    let y: Vec<f64> = flip_angles.iter().zip(signal).map(|(a, s)| s / a.sin()).collect();
+    let (e1, intercept) = linear_fit(&x, &y);
+    (-tr / e1.ln(), intercept / (1.0 - e1))
+}
+
+pub fn simulate(params: Despot1Params) -> Despot1Signal {
+    assert!(params.flip_angles.len() >= 2, "at least two flip angles are needed");
+    assert!(params.npulses > 0, "at least one pulse must be simulated");
+
+    let signal: Vec<Complex64> = params
+        .flip_angles
+        .iter()
+        .map(|&alpha| {
+            spoiled_steady_state(
+                params.b1 * alpha,
+                params.tr,
+                params.t1,
+                params.t2,
+                params.spoiling,
+                params.npulses,
+                params.n_states,
+            )
+        })
+        .collect();
+
+    let real: Vec<f64> = signal.iter().map(|s| s.re).collect();
+    let (t1_estimate, m0_estimate) = estimate(&params.flip_angles, &real, params.tr);
+
+    if params.debug_print {
+        println!("Signal: {:?}", signal);
+        println!("T1 {:.4} M0 {:.4}", t1_estimate, m0_estimate);
+    }
+
+    Despot1Signal {
+        signal,
+        t1_estimate,
+        m0_estimate,
+    }
+}
+
+/// Reports the steady state of each flip angle. The acquisitions are independent,
+/// each from equilibrium.
+impl Sequence for Despot1Params {
+    fn name(&self) -> &'static str {
+        "DESPOT1"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.flip_angles.len() < 2 {
+            return Err("at least two flip angles are needed".into());
+        }
+        if self.tr <= 0.0 {
+            return Err("TR must be positive".into());
+        }
+        if self.npulses == 0 {
+            return Err("at least one pulse must be simulated".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let mut timeline = Timeline::new();
+        for (ix, &alpha) in self.flip_angles.iter().enumerate() {
+            if ix > 0 {
+                timeline.reset();
+            }
+            timeline.spoiled_steady_state(self.b1 * alpha, self.tr, self.spoiling, self.npulses);
+        }
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        (self.flip_angles.len() * self.npulses) as f64 * self.tr
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        simulate(Despot1Params {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.clone()
+        })
+        .signal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn params(b1: f64, spoiling: Spoiling) -> Despot1Params {
+        Despot1Params {
+            t1: 0.9,
+            t2: 0.06,
+            flip_angles: vec![3.0_f64.to_radians(), 15.0_f64.to_radians()],
+            tr: 0.01,
+            b1,
+            spoiling,
+            npulses: 1000,
+            n_states: 80,
+            debug_print: false,
+        }
+    }
+
+    #[test]
+    fn test_ideal_spoiling_exact() {
+        let res = simulate(params(1.0, Spoiling::Ideal));
+        assert!((res.t1_estimate - 0.9).abs() < 1e-6);
+        assert!((res.m0_estimate - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_b1_and_spoiling_bias() {
+        // T1 scales with roughly b1^2
+        let low_b1 = simulate(params(0.9, Spoiling::Ideal));
+        assert!((low_b1.t1_estimate / 0.9 - 0.81).abs() < 0.02);
+
+        let unspoiled = simulate(params(
+            1.0,
+            Spoiling::Gradient {
+                rf_increment: PI,
+                twists: 1,
+            },
+        ));
+        assert!((unspoiled.t1_estimate - 0.9).abs() > 0.01);
+    }
+}
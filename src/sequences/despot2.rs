@@ -0,0 +1,164 @@
+use num_complex::Complex64;
+
+use super::common::{balanced_ssfp_train, linear_fit, Timeline};
+use super::{Event, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, EPG},
+};
+
+/// Variable flip angle balanced SSFP protocol.
+#[derive(Clone)]
+pub struct Despot2Params {
+    pub t1: f64,
+    pub t2: f64,
+    /// Nominal flip angles of the protocol.
+    pub flip_angles: Vec<f64>,
+    pub tr: f64,
+    /// Relative transmit field. The estimator assumes the nominal flip angles.
+    pub b1: f64,
+    /// T1 given to the estimator, e.g. from DESPOT1.
+    pub t1_estimate: f64,
+    /// Number of pulses simulated per flip angle. The last one is reported.
+    pub npulses: usize,
+    pub debug_print: bool,
+}
+
+pub struct Despot2Signal {
+    /// Steady state signal for each flip angle, read at TE = TR/2.
+    pub signal: Vec<Complex64>,
+    pub t2_estimate: f64,
+    /// Apparent M0, including the T2 decay to TE.
+    pub m0_estimate: f64,
+}
+
+/// Linear DESPOT2 fit of `S / sin(a)` against `S / tan(a)` with known T1. The slope is
+/// `(E1 - E2) / (1 - E1 E2)` and the intercept `M0 (1 - E1) / (1 - E1 E2)`.
+/// Returns (T2, M0).
+pub fn estimate(flip_angles: &[f64], signal: &[f64], tr: f64, t1: f64) -> (f64, f64) {
+    let x: Vec<f64> = flip_angles.iter().zip(signal).map(|(a, s)| s / a.tan()).collect();
+    let y: Vec<f64> = flip_angles.iter().zip(signal).map(|(a, s)| s / a.sin()).collect();
+    let (slope, intercept) = linear_fit(&x, &y);
+
+    let e1 = (-tr / t1).exp();
+    let e2 = (e1 - slope) / (1.0 - slope * e1);
+    (-tr / e2.ln(), intercept * (1.0 - e1 * e2) / (1.0 - e1))
+}
+
+pub fn simulate(params: Despot2Params) -> Despot2Signal {
+    assert!(params.flip_angles.len() >= 2, "at least two flip angles are needed");
+    assert!(params.npulses > 0, "at least one pulse must be simulated");
+
+    let signal: Vec<Complex64> = params
+        .flip_angles
+        .iter()
+        .map(|&alpha| {
+            let mut epg = epg::vec::EPGVecRepresentation::new(3);
+            let train = balanced_ssfp_train(
+                &mut epg,
+                params.b1 * alpha,
+                params.tr,
+                params.npulses,
+                params.t1,
+                params.t2,
+            );
+            train[params.npulses - 1]
+        })
+        .collect();
+
+    let real: Vec<f64> = signal.iter().map(|s| s.re).collect();
+    let (t2_estimate, m0_estimate) =
+        estimate(&params.flip_angles, &real, params.tr, params.t1_estimate);
+
+    if params.debug_print {
+        println!("Signal: {:?}", signal);
+        println!("T2 {:.4} M0 {:.4}", t2_estimate, m0_estimate);
+    }
+
+    Despot2Signal {
+        signal,
+        t2_estimate,
+        m0_estimate,
+    }
+}
+
+/// Reports the steady state of each flip angle. The acquisitions are independent,
+/// each from equilibrium.
+impl Sequence for Despot2Params {
+    fn name(&self) -> &'static str {
+        "DESPOT2"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.flip_angles.len() < 2 {
+            return Err("at least two flip angles are needed".into());
+        }
+        if self.tr <= 0.0 {
+            return Err("TR must be positive".into());
+        }
+        if self.npulses == 0 {
+            return Err("at least one pulse must be simulated".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let last = self.npulses - 1;
+        let mut timeline = Timeline::new();
+        for (ix, &alpha) in self.flip_angles.iter().enumerate() {
+            if ix > 0 {
+                timeline.reset();
+            }
+            timeline.balanced_ssfp_train(self.b1 * alpha, self.tr, self.npulses, last..last + 1);
+        }
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        self.flip_angles.len() as f64 * (self.npulses as f64 + 0.5) * self.tr
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        simulate(Despot2Params {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.clone()
+        })
+        .signal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(b1: f64, t1_estimate: f64) -> Despot2Params {
+        Despot2Params {
+            t1: 0.9,
+            t2: 0.06,
+            flip_angles: vec![15.0_f64.to_radians(), 60.0_f64.to_radians()],
+            tr: 0.005,
+            b1,
+            t1_estimate,
+            npulses: 4000,
+            debug_print: false,
+        }
+    }
+
+    #[test]
+    fn test_known_t1_recovers_t2() {
+        let res = simulate(params(1.0, 0.9));
+        assert!((res.t2_estimate - 0.06).abs() / 0.06 < 0.01);
+    }
+
+    #[test]
+    fn test_b1_and_t1_bias() {
+        // low B1 overestimates T2
+        let low_b1 = simulate(params(0.9, 0.9));
+        assert!((low_b1.t2_estimate / 0.06 - 1.26).abs() < 0.02);
+
+        // the relative T1 error carries over to T2
+        let long_t1 = simulate(params(1.0, 1.0));
+        assert!((long_t1.t2_estimate / 0.06 - 1.11).abs() < 0.02);
+    }
+}
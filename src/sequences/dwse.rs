@@ -0,0 +1,206 @@
+use num_complex::Complex64;
+
+use std::f64::consts::PI;
+
+use super::common::{diffusion_interval, Timeline};
+use super::fat::GAMMA_HZ;
+use super::{Event, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, TissueProperties, EPG},
+};
+
+/// Pair of rectangular diffusion gradient lobes.
+#[derive(Clone, Copy)]
+pub struct DiffusionGradient {
+    /// Gradient amplitude in T/m.
+    pub strength: f64,
+    /// Duration of each lobe (little delta).
+    pub duration: f64,
+    /// Onset-to-onset separation of the lobes (big delta).
+    pub separation: f64,
+}
+
+impl DiffusionGradient {
+    /// Wavenumber in rad/m imparted by one lobe.
+    pub fn dk(&self) -> f64 {
+        2.0 * PI * GAMMA_HZ * self.strength * self.duration
+    }
+
+    /// Stejskal-Tanner b-value in s/m^2.
+    pub fn b_value(&self) -> f64 {
+        self.dk().powi(2) * (self.separation - self.duration / 3.0)
+    }
+
+    /// Diffusion is not played by the events, so they only describe tissues without
+    /// diffusion weighting.
+    pub(crate) fn validate_events(&self, tissue: &TissueProperties) -> Result<(), String> {
+        let weighted = self.strength != 0.0 && tissue.pools().iter().any(|c| c.adc > 0.0);
+        if weighted {
+            return Err(format!("{}: diffusion is not modelled by the events", tissue.name));
+        }
+        Ok(())
+    }
+}
+
+/// Diffusion weighted spin echo. The lobes are centred either side of the refocusing pulse.
+#[derive(Clone)]
+pub struct DwSeParams {
+    pub t1: f64,
+    pub t2: f64,
+    /// Apparent diffusion coefficient in m^2/s.
+    pub adc: f64,
+    pub echo_time: f64,
+    pub gradient: DiffusionGradient,
+    pub debug_print: bool,
+}
+
+/// Signal at one point of a b-value sweep.
+pub struct BPoint {
+    /// b-value in s/m^2.
+    pub b: f64,
+    pub signal: Complex64,
+    /// `|S(b = 0)| exp(-b ADC)`.
+    pub monoexponential: f64,
+}
+
+pub fn simulate(params: DwSeParams) -> Vec<Complex64> {
+    let te = params.echo_time;
+    let g = params.gradient;
+    let start = te / 2.0 - (g.separation + g.duration) / 2.0;
+
+    assert!(g.separation >= g.duration, "lobes overlap");
+    assert!(start >= 0.0, "diffusion gradients do not fit in the echo time");
+
+    let mut epg = epg::vec::EPGVecRepresentation::new(3);
+    let mut signal: Vec<Complex64> = Vec::with_capacity(1);
+
+    let x180 = epg::common::gen_rotation_matrix(PI, PI / 2.0);
+    let dk = g.dk();
+    let interval = |epg: &mut epg::vec::EPGVecRepresentation, dt: f64, ntwists: i32| {
+        diffusion_interval(epg, dt, params.t1, params.t2, params.adc, dk, ntwists)
+    };
+
+    epg.excite();
+    interval(&mut epg, start, 0);
+    interval(&mut epg, g.duration, 1);
+    interval(&mut epg, te / 2.0 - start - g.duration, 0);
+    epg.rotate(&x180);
+    interval(&mut epg, start + g.separation - te / 2.0, 0);
+    interval(&mut epg, g.duration, 1);
+    interval(&mut epg, te - start - g.separation - g.duration, 0);
+
+    signal.push(epg.read());
+
+    if params.debug_print {
+        println!("b {:.4e} Signal: {:?}", g.b_value(), signal);
+        println!("{:}", epg::vec::to_mxy(&epg));
+        println!("{:}", epg::vec::to_mz(&epg));
+    }
+
+    signal
+}
+
+impl Sequence for DwSeParams {
+    fn name(&self) -> &'static str {
+        "DWSE"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let g = self.gradient;
+        if g.separation < g.duration {
+            return Err("lobes overlap".into());
+        }
+        if self.echo_time < g.separation + g.duration {
+            return Err("diffusion gradients do not fit in the echo time".into());
+        }
+        Ok(())
+    }
+
+    fn validate_events(&self, tissue: &TissueProperties) -> Result<(), String> {
+        self.gradient.validate_events(tissue)
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let te = self.echo_time;
+        let g = self.gradient;
+        let start = te / 2.0 - (g.separation + g.duration) / 2.0;
+
+        let mut timeline = Timeline::new();
+        timeline.excite();
+        timeline.delay(start);
+        timeline.gradient(g.duration, 1);
+        timeline.delay(te / 2.0 - start - g.duration);
+        timeline.pulse(PI, PI / 2.0);
+        timeline.delay(start + g.separation - te / 2.0);
+        timeline.gradient(g.duration, 1);
+        timeline.delay(te - start - g.separation - g.duration);
+        timeline.readout(0.0);
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        self.echo_time
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        simulate(DwSeParams {
+            t1: pool.t1,
+            t2: pool.t2,
+            adc: pool.adc,
+            ..self.clone()
+        })
+    }
+}
+
+/// Sweep the gradient strength, keeping lobe timing, and compare each signal with the
+/// mono-exponential model.
+pub fn b_sweep(params: &DwSeParams, strengths: &[f64]) -> Vec<BPoint> {
+    let with_strength = |strength: f64| {
+        let mut p = params.clone();
+        p.gradient.strength = strength;
+        p.debug_print = false;
+        p
+    };
+    let s0 = simulate(with_strength(0.0))[0].norm();
+
+    strengths
+        .iter()
+        .map(|&strength| {
+            let p = with_strength(strength);
+            let b = p.gradient.b_value();
+            BPoint {
+                b,
+                signal: simulate(p)[0],
+                monoexponential: s0 * (-b * params.adc).exp(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_stejskal_tanner() {
+        let params = DwSeParams {
+            t1: 1.0,
+            t2: 0.08,
+            adc: 0.8e-9,
+            echo_time: 0.08,
+            gradient: DiffusionGradient {
+                strength: 0.0,
+                duration: 0.02,
+                separation: 0.035,
+            },
+            debug_print: false,
+        };
+
+        let sweep = b_sweep(&params, &[0.0, 0.01, 0.02, 0.04]);
+        assert!(sweep[3].b > 1e9);
+        for point in sweep.iter() {
+            assert!((point.signal.norm() - point.monoexponential).abs() < 1e-9);
+        }
+    }
+}
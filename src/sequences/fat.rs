@@ -0,0 +1,550 @@
+use num_complex::{Complex, Complex64};
+
+use std::f64::consts::PI;
+
+use super::common::{relaxation_factors, EchoTrain, RfSpoiler, Timeline, RF_SPOIL_INCREMENT};
+use super::dephasing::Dephasing;
+use super::{Event, Preparation, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, TissueProperties, EPG},
+};
+
+/// Proton gyromagnetic ratio in Hz/T.
+pub const GAMMA_HZ: f64 = 42.577_478e6;
+
+/// Chemical shift spectrum of fat, relative to water.
+#[derive(Clone)]
+pub struct FatSpectrum {
+    pub ppm: Vec<f64>,
+    /// Relative amplitude of each peak, summing to one.
+    pub amplitudes: Vec<f64>,
+}
+
+impl FatSpectrum {
+    /// Six peak liver fat spectrum (Hamilton et al. 2011).
+    pub fn hamilton() -> Self {
+        Self {
+            ppm: vec![-3.80, -3.40, -2.60, -1.94, -0.39, 0.60],
+            amplitudes: vec![0.087, 0.693, 0.128, 0.004, 0.039, 0.048],
+        }
+    }
+
+    /// Peak frequencies in Hz at field strength `b0` (Tesla).
+    pub fn frequencies(&self, b0: f64) -> Vec<f64> {
+        self.ppm.iter().map(|ppm| ppm * 1e-6 * GAMMA_HZ * b0).collect()
+    }
+}
+
+/// Fat compartment. J-coupling is not modelled, so every peak refocuses at spin echoes.
+#[derive(Clone)]
+pub struct FatModel {
+    pub spectrum: FatSpectrum,
+    pub t1: f64,
+    pub t2: f64,
+    /// Apparent decay of the gradient echoes, at most `t2`.
+    pub t2s: f64,
+    /// Fat share of the proton density, the rest being water.
+    pub fraction: f64,
+}
+
+/// Frequency band hit by a spectrally selective pulse, in ppm relative to water.
+#[derive(Clone, Copy)]
+pub struct SpectralBand {
+    pub center_ppm: f64,
+    pub width_ppm: f64,
+}
+
+impl SpectralBand {
+    pub fn contains(&self, ppm: f64) -> bool {
+        (ppm - self.center_ppm).abs() <= self.width_ppm / 2.0
+    }
+}
+
+/// Fat suppression preparation played before every shot. As a `Preparation` in front of
+/// another sequence it acts on the water resonance of the simulated tissue.
+#[derive(Clone, Copy)]
+pub enum FatSuppression {
+    None,
+    /// Spectrally selective excitation and crusher, `delay` before the readout.
+    Chess {
+        band: SpectralBand,
+        flip_angle: f64,
+        delay: f64,
+    },
+    /// Spectrally selective adiabatic inversion.
+    Spair {
+        band: SpectralBand,
+        inversion_time: f64,
+    },
+    /// Non-selective short-TI inversion recovery.
+    Stir { inversion_time: f64 },
+}
+
+impl FatSuppression {
+    /// Time taken by the preparation before the readout starts.
+    pub fn duration(&self) -> f64 {
+        match *self {
+            FatSuppression::None => 0.0,
+            FatSuppression::Chess { delay, .. } => delay,
+            FatSuppression::Spair { inversion_time, .. } => inversion_time,
+            FatSuppression::Stir { inversion_time } => inversion_time,
+        }
+    }
+
+    /// Apply the preparation to a species resonating at `ppm`. Without suppression
+    /// nothing is played, not even the crusher.
+    pub(crate) fn apply<E: EPG>(&self, epg: &mut E, ppm: f64, t1: f64, t2: f64) {
+        let Some(flip_angle) = self.flip_angle(ppm) else {
+            return;
+        };
+
+        let rf = epg::common::gen_rotation_matrix(flip_angle, 0.0);
+        let (et1d, et2d) = relaxation_factors(self.duration(), t1, t2);
+        epg.rotate(&rf);
+        epg.crush();
+        epg.delay(et1d, et2d);
+    }
+
+    /// Events of `apply`.
+    pub(crate) fn play(&self, timeline: &mut Timeline, ppm: f64) {
+        let Some(flip_angle) = self.flip_angle(ppm) else {
+            return;
+        };
+
+        timeline.pulse(flip_angle, 0.0);
+        timeline.crush();
+        timeline.delay(self.duration());
+    }
+
+    /// Flip angle seen at `ppm`, `None` when nothing is played.
+    pub fn flip_angle(&self, ppm: f64) -> Option<f64> {
+        match *self {
+            FatSuppression::None => None,
+            FatSuppression::Chess {
+                band, flip_angle, ..
+            } if band.contains(ppm) => Some(flip_angle),
+            FatSuppression::Spair { band, .. } if band.contains(ppm) => Some(PI),
+            FatSuppression::Stir { .. } => Some(PI),
+            _ => Some(0.0),
+        }
+    }
+}
+
+impl Preparation for FatSuppression {
+    fn validate(&self) -> Result<(), String> {
+        let band = match *self {
+            FatSuppression::Chess { band, .. } | FatSuppression::Spair { band, .. } => Some(band),
+            _ => None,
+        };
+        if band.is_some_and(|b| b.width_ppm <= 0.0) {
+            return Err("spectral band must have a positive width".into());
+        }
+        if FatSuppression::duration(self) < 0.0 {
+            return Err("fat suppression delay must not be negative".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let mut timeline = Timeline::new();
+        self.play(&mut timeline, 0.0);
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        FatSuppression::duration(self)
+    }
+}
+
+/// Readout protocol following the fat suppression.
+#[derive(Clone, Copy)]
+pub enum FatProtocol {
+    /// CPMG echo train, `tr` between preparations.
+    Fse {
+        etl: usize,
+        esp: f64,
+        refocus_angle: f64,
+        cpmg_phase: f64,
+        tr: f64,
+    },
+    /// Segment of RF-spoiled gradient echoes read at `te`, with a preparation per segment.
+    Spgr {
+        flip_angle: f64,
+        tr: f64,
+        te: f64,
+        nreads: usize,
+    },
+}
+
+#[derive(Clone)]
+pub struct FatSatParams {
+    pub water_t1: f64,
+    pub water_t2: f64,
+    /// Apparent decay of the water gradient echoes, at most `water_t2`.
+    pub water_t2s: f64,
+    pub fat: FatModel,
+    /// Field strength in Tesla.
+    pub b0: f64,
+    pub suppression: FatSuppression,
+    pub protocol: FatProtocol,
+    /// Number of shots simulated. The last shot is reported.
+    pub nshots: usize,
+    pub debug_print: bool,
+}
+
+pub struct FatSatSignal {
+    pub water: Vec<Complex64>,
+    /// Amplitude-weighted sum over the fat peaks.
+    pub fat: Vec<Complex64>,
+}
+
+impl FatSatParams {
+    /// Echo train of the FSE protocol for a species at `ppm`, `None` for other protocols.
+    fn echo_train(&self, ppm: f64, t1: f64, t2: f64) -> Option<EchoTrain> {
+        let FatProtocol::Fse {
+            etl,
+            esp,
+            refocus_angle,
+            cpmg_phase,
+            tr,
+        } = self.protocol
+        else {
+            return None;
+        };
+        let freq = ppm * 1e-6 * GAMMA_HZ * self.b0;
+        Some(EchoTrain {
+            etl,
+            t1,
+            t2,
+            esp,
+            refocus_angle,
+            cpmg_phase,
+            restore: false,
+            tr,
+            nshots: self.nshots,
+            prep: self.suppression.flip_angle(ppm).map(|a| vec![(a, 0.0)]).unwrap_or_default(),
+            prep_time: self.suppression.duration(),
+            precession: 2.0 * PI * freq * esp / 2.0,
+        })
+    }
+}
+
+/// Signal of a single species at chemical shift `ppm`.
+fn species(params: &FatSatParams, ppm: f64, t1: f64, t2: f64, t2s: f64) -> Vec<Complex64> {
+    let mut signal: Vec<Complex64> = Vec::new();
+    let freq = ppm * 1e-6 * GAMMA_HZ * params.b0;
+
+    match params.protocol {
+        FatProtocol::Fse { .. } => {
+            let train = params.echo_train(ppm, t1, t2).expect("FSE protocol");
+            signal = train.run(false).echoes;
+        }
+        FatProtocol::Spgr {
+            flip_angle,
+            tr,
+            te,
+            nreads,
+        } => {
+            let mut epg = epg::vec::EPGVecRepresentation::new(nreads + 1);
+            let mut spoiler = RfSpoiler::new(RF_SPOIL_INCREMENT);
+            let (et1_te, et2_te) = relaxation_factors(te, t1, t2);
+            let (et1_tr, et2_tr) = relaxation_factors(tr - te, t1, t2);
+            let decay = Dephasing::from_t2s(t2, t2s).decay(te);
+
+            for _ in 0..params.nshots {
+                signal.clear();
+                params.suppression.apply(&mut epg, ppm, t1, t2);
+                for _ in 0..nreads {
+                    let rf_phase = spoiler.next_phase();
+                    let rf = epg::common::gen_rotation_matrix(flip_angle, PI / 2.0 + rf_phase);
+                    epg.rotate(&rf);
+                    epg.delay(et1_te, et2_te);
+                    epg.precess(2.0 * PI * freq * te);
+                    signal.push(decay * epg.read() * (-Complex::i() * rf_phase).exp());
+                    epg.grelax(et1_tr, et2_tr, 1);
+                    epg.precess(2.0 * PI * freq * (tr - te));
+                }
+            }
+        }
+    }
+
+    signal
+}
+
+pub fn simulate(params: FatSatParams) -> FatSatSignal {
+    assert!(params.nshots > 0, "at least one shot must be simulated");
+
+    let water = species(&params, 0.0, params.water_t1, params.water_t2, params.water_t2s);
+
+    let spectrum = &params.fat.spectrum;
+    let mut fat = vec![Complex64::from(0.0); water.len()];
+    for (&ppm, amplitude) in spectrum.ppm.iter().zip(spectrum.amplitudes.iter()) {
+        let peak = species(&params, ppm, params.fat.t1, params.fat.t2, params.fat.t2s);
+        for (f, p) in fat.iter_mut().zip(peak) {
+            *f += amplitude * p;
+        }
+    }
+
+    if params.debug_print {
+        println!("Water: {:?}", water);
+        println!("Fat: {:?}", fat);
+    }
+
+    FatSatSignal { water, fat }
+}
+
+/// Reports the signal of the last shot, the tissue being simulated as water and the fat
+/// model of the parameters added by its fraction. The events describe the water only.
+impl Sequence for FatSatParams {
+    fn name(&self) -> &'static str {
+        "FatSat"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.nshots == 0 {
+            return Err("at least one shot must be simulated".into());
+        }
+        if !(0.0..=1.0).contains(&self.fat.fraction) {
+            return Err("fat fraction must lie between zero and one".into());
+        }
+        Preparation::validate(&self.suppression)?;
+        match self.protocol {
+            FatProtocol::Fse { .. } => {
+                let train = self.echo_train(0.0, self.water_t1, self.water_t2);
+                train.expect("FSE protocol").validate()?;
+            }
+            FatProtocol::Spgr { tr, te, nreads, .. } => {
+                if nreads == 0 {
+                    return Err("at least one readout per segment".into());
+                }
+                if te < 0.0 || te > tr {
+                    return Err("echo time must lie within TR".into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_events(&self, tissue: &TissueProperties) -> Result<(), String> {
+        if self.fat.fraction > 0.0 {
+            return Err(format!("{}: the events do not describe the fat peaks", tissue.name));
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        if let Some(train) = self.echo_train(0.0, self.water_t1, self.water_t2) {
+            return train.timeline().into_events();
+        }
+        let FatProtocol::Spgr {
+            flip_angle,
+            tr,
+            te,
+            nreads,
+        } = self.protocol
+        else {
+            unreachable!("FSE events are those of its echo train");
+        };
+
+        let mut timeline = Timeline::new();
+        let mut spoiler = RfSpoiler::new(RF_SPOIL_INCREMENT);
+        for shot in 0..self.nshots {
+            timeline.record = shot + 1 == self.nshots;
+            self.suppression.play(&mut timeline, 0.0);
+            for _ in 0..nreads {
+                let phase = spoiler.next_phase();
+                timeline.pulse(flip_angle, PI / 2.0 + phase);
+                timeline.delay(te);
+                timeline.gradient_echo(te, phase);
+                timeline.gradient(tr - te, 1);
+            }
+        }
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        let shot = match self.protocol {
+            FatProtocol::Fse { tr, .. } => tr,
+            FatProtocol::Spgr { tr, nreads, .. } => {
+                self.suppression.duration() + nreads as f64 * tr
+            }
+        };
+        self.nshots as f64 * shot
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        let res = simulate(FatSatParams {
+            water_t1: pool.t1,
+            water_t2: pool.t2,
+            water_t2s: pool.t2s,
+            ..self.clone()
+        });
+        let fraction = self.fat.fraction;
+        res.water
+            .iter()
+            .zip(res.fat.iter())
+            .map(|(w, f)| (1.0 - fraction) * w + fraction * f)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::fse::FseParams;
+    use crate::sequences::Prepared;
+
+    const FAT_BAND: SpectralBand = SpectralBand {
+        center_ppm: -3.4,
+        width_ppm: 2.0,
+    };
+
+    fn params(suppression: FatSuppression, protocol: FatProtocol) -> FatSatParams {
+        FatSatParams {
+            water_t1: 1.0,
+            water_t2: 0.05,
+            water_t2s: 0.05,
+            fat: FatModel {
+                spectrum: FatSpectrum::hamilton(),
+                t1: 0.38,
+                t2: 0.08,
+                t2s: 0.08,
+                fraction: 0.2,
+            },
+            b0: 3.0,
+            suppression,
+            protocol,
+            nshots: 4,
+            debug_print: false,
+        }
+    }
+
+    const FSE: FatProtocol = FatProtocol::Fse {
+        etl: 16,
+        esp: 0.01,
+        refocus_angle: 2.6,
+        cpmg_phase: PI / 2.0,
+        tr: 3.0,
+    };
+
+    #[test]
+    fn test_bright_fat_in_fse() {
+        // without J-coupling every peak refocuses at the echoes, so fat decays with its
+        // own T2 alone and outlasts the water
+        let mut p = params(
+            FatSuppression::None,
+            FatProtocol::Fse {
+                etl: 16,
+                esp: 0.01,
+                refocus_angle: PI,
+                cpmg_phase: PI / 2.0,
+                tr: 0.16,
+            },
+        );
+        p.nshots = 1;
+        let res = simulate(p.clone());
+
+        let amplitude: f64 = p.fat.spectrum.amplitudes.iter().sum();
+        let t = 16.0 * 0.01;
+        let (water, fat) = ((-t / 0.05_f64).exp(), amplitude * (-t / 0.08_f64).exp());
+        assert!((res.water[15].norm() - water).abs() < 1e-9);
+        assert!((res.fat[15].norm() - fat).abs() < 1e-9);
+        assert!(fat > water);
+
+        // the sequence adds the fat by its fraction, in phase with the water
+        let pool = Compartment {
+            name: "water".into(),
+            fraction: 1.0,
+            t1: 1.0,
+            t2: 0.05,
+            t2s: 0.05,
+            adc: 0.0,
+        };
+        let signal = p.signal(&pool);
+        assert!((signal[15].norm() - (0.8 * water + 0.2 * fat)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_spair_nulls_main_peak() {
+        let ti = 0.38 * 2.0_f64.ln();
+        let res = simulate(params(
+            FatSuppression::Spair {
+                band: FAT_BAND,
+                inversion_time: ti,
+            },
+            FSE,
+        ));
+        let plain = simulate(params(FatSuppression::None, FSE));
+
+        // only the peaks outside the band remain, water is untouched
+        assert!(res.fat[0].norm() < 0.15 * plain.fat[0].norm());
+        assert!((res.water[0] - plain.water[0]).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_stir_suppresses_water_too() {
+        let ti = 0.38 * 2.0_f64.ln();
+        let spgr = FatProtocol::Spgr {
+            flip_angle: 15.0_f64.to_radians(),
+            tr: 0.01,
+            te: 0.00246,
+            nreads: 1,
+        };
+        // a single segment from equilibrium, so T1 ln(2) is the null point
+        let mut stir = params(FatSuppression::Stir { inversion_time: ti }, spgr);
+        stir.nshots = 1;
+        let res = simulate(stir);
+        let mut plain = params(FatSuppression::None, spgr);
+        plain.nshots = 1;
+        let plain = simulate(plain);
+
+        // water recovers from the inversion with its own T1
+        let water_mz = 1.0 - 2.0 * (-ti / 1.0_f64).exp();
+        assert!((res.water[0].norm() - water_mz.abs() * plain.water[0].norm()).abs() < 1e-12);
+        assert!(res.fat[0].norm() < 0.1 * plain.fat[0].norm());
+    }
+
+    #[test]
+    fn test_spgr_reads_with_t2_star() {
+        let spgr = FatProtocol::Spgr {
+            flip_angle: 15.0_f64.to_radians(),
+            tr: 0.01,
+            te: 0.005,
+            nreads: 1,
+        };
+        let mut p = params(FatSuppression::None, spgr);
+        p.nshots = 1;
+        p.water_t2s = 0.02;
+        let res = simulate(p);
+
+        // T2 and T2' together decay the first echo with T2*
+        let expected = 15.0_f64.to_radians().sin() * (-0.005_f64 / 0.02).exp();
+        assert!((res.water[0].norm() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_stir_in_front_of_fse() {
+        let ti = 0.38 * 2.0_f64.ln();
+        let fse = FseParams::new(8, 1.0, 0.05, 0.01, PI, PI / 2.0);
+        let prepared = Prepared {
+            prep: FatSuppression::Stir { inversion_time: ti },
+            sequence: fse.clone(),
+        };
+        let pool = Compartment {
+            name: "water".into(),
+            fraction: 1.0,
+            t1: 1.0,
+            t2: 0.05,
+            t2s: 0.05,
+            adc: 0.0,
+        };
+
+        let water_mz = 1.0 - 2.0 * (-ti / 1.0_f64).exp();
+        let signal = prepared.signal(&pool);
+        let plain = fse.signal(&pool);
+        for (s, p) in signal.iter().zip(plain.iter()) {
+            assert!((s - water_mz * p).norm() < 1e-9);
+        }
+    }
+}
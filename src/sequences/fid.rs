@@ -1,37 +1,95 @@
 use num_complex::Complex64;
 
-use crate::{epg, types::EPG};
+use std::f64::consts::PI;
 
+use super::dephasing::Dephasing;
+use super::{Event, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, EPG},
+};
+
+#[derive(Clone)]
 pub struct FidParams {
     pub nreads: usize,
     pub t1: f64,
     pub t2: f64,
     pub echo_time: f64,
+    /// Reversible dephasing, decaying the signal with T2*.
+    pub dephasing: Dephasing,
     pub debug_print: bool,
 }
 
 pub fn simulate(params: FidParams) -> Vec<Complex64> {
     let nreads = params.nreads;
 
-    let mut epg = epg::vec::EPGVecRepresentation::new(nreads + 1);
-    let mut signal: Vec<Complex64> = Vec::with_capacity(nreads + 1);
+    let mut signal: Vec<Complex64> = vec![Complex64::from(0.0); nreads];
 
     let dt = params.echo_time;
     let et1d = Complex64::from((-dt / params.t1).exp());
     let et2d = Complex64::from((-dt / params.t2).exp());
 
-    epg.excite();
+    for (omega, weight) in params.dephasing.isochromats() {
+        let mut epg = epg::vec::EPGVecRepresentation::new(nreads + 1);
+        epg.excite();
 
-    for _ in 0..nreads {
-        epg.grelax(et1d, et2d, 0);
-        signal.push(epg.read());
+        for (ix, s) in signal.iter_mut().enumerate() {
+            epg.grelax(et1d, et2d, 0);
+            epg.precess(omega * dt);
+            let tau = (ix + 1) as f64 * dt;
+            *s += weight * params.dephasing.decay(tau) * epg.read();
+        }
     }
 
     if params.debug_print {
         println!("Signal: {:?}", signal);
-        println!("{:}", epg::vec::to_mxy(&epg));
-        println!("{:}", epg::vec::to_mz(&epg));
     }
 
-    return signal;
+    signal
+}
+
+impl Sequence for FidParams {
+    fn name(&self) -> &'static str {
+        "FID"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.nreads == 0 {
+            return Err("FID needs at least one readout".into());
+        }
+        if self.echo_time <= 0.0 {
+            return Err("echo time must be positive".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let mut events = vec![Event::Pulse {
+            time: 0.0,
+            flip_angle: PI / 2.0,
+            phase: PI / 2.0,
+        }];
+        for ix in 1..=self.nreads {
+            let time = ix as f64 * self.echo_time;
+            events.push(Event::Readout {
+                time,
+                phase: 0.0,
+                tau: time,
+            });
+        }
+        events
+    }
+
+    fn duration(&self) -> f64 {
+        self.nreads as f64 * self.echo_time
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        simulate(FidParams {
+            t1: pool.t1,
+            t2: pool.t2,
+            dephasing: Dephasing::from_t2s(pool.t2, pool.t2s),
+            ..self.clone()
+        })
+    }
 }
@@ -1,8 +1,10 @@
 use num_complex::Complex64;
 
-use crate::{epg, types::EPG};
-
+use super::common::EchoTrain;
+use super::{Event, Sequence};
+use crate::types::Compartment;
 
+#[derive(Clone)]
 pub struct FseParams {
     pub etl: usize,
     pub t1: f64,
@@ -10,40 +12,121 @@ pub struct FseParams {
     pub esp: f64,
     pub refocus_angle: f64,
     pub cpmg_phase: f64,
+    /// Play a -90 degree restore pulse, phased to the last echo, at the end of the train.
+    pub restore: bool,
+    /// Shot repetition time.
+    pub tr: f64,
+    /// Number of shots simulated. The last shot is reported.
+    pub nshots: usize,
     pub debug_print: bool,
 }
 
+impl FseParams {
+    /// Single shot without restore, TR set to the echo train length.
+    pub fn new(
+        etl: usize,
+        t1: f64,
+        t2: f64,
+        esp: f64,
+        refocus_angle: f64,
+        cpmg_phase: f64,
+    ) -> Self {
+        Self {
+            etl,
+            t1,
+            t2,
+            esp,
+            refocus_angle,
+            cpmg_phase,
+            restore: false,
+            tr: etl as f64 * esp,
+            nshots: 1,
+            debug_print: false,
+        }
+    }
+
+    fn train(&self) -> EchoTrain {
+        EchoTrain {
+            etl: self.etl,
+            t1: self.t1,
+            t2: self.t2,
+            esp: self.esp,
+            refocus_angle: self.refocus_angle,
+            cpmg_phase: self.cpmg_phase,
+            restore: self.restore,
+            tr: self.tr,
+            nshots: self.nshots,
+            prep: vec![],
+            prep_time: 0.0,
+            precession: 0.0,
+        }
+    }
+}
+
+pub fn simulate(params: FseParams) -> Vec<Complex64> {
+    params.train().run(params.debug_print).echoes
+}
 
-pub fn simulate(params: FseParams) -> Vec<Complex64>{
-    
-    let etl = params.etl;
+/// Longitudinal magnetization available to the excitation of the shot after the last one.
+pub fn mz_at_next_shot(params: &FseParams) -> f64 {
+    params.train().run(params.debug_print).mz_next
+}
 
-    let mut epg = epg::vec::EPGVecRepresentation::new(etl / 2 + 1);
+impl Sequence for FseParams {
+    fn name(&self) -> &'static str {
+        "FSE"
+    }
 
-    let mut signal: Vec<Complex64> = Vec::with_capacity(etl + 1);
+    fn validate(&self) -> Result<(), String> {
+        self.train().validate()
+    }
 
-    let x180 = epg::common::gen_rotation_matrix(params.refocus_angle, params.cpmg_phase);
+    fn events(&self) -> Vec<Event> {
+        self.train().timeline().into_events()
+    }
 
-    // dt is the spacing of our events, unsed for dephasing/relaxation
-    let dt = params.esp / 2.0;
-    let et1d = Complex64::from((-dt / params.t1).exp());
-    let et2d = Complex64::from((-dt / params.t2).exp());
+    fn duration(&self) -> f64 {
+        self.nshots as f64 * self.tr
+    }
 
-    epg.excite();
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        EchoTrain {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.train()
+        }
+        .run(false)
+        .echoes
+    }
+}
 
-    for _ in 0..etl {
-        epg.grelax(et1d, et2d, 1);
-        epg.rotate(&x180);
-        epg.grelax(et1d, et2d, 1);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
 
-        signal.push(epg.read());
+    fn fluid(restore: bool, nshots: usize) -> FseParams {
+        FseParams {
+            restore,
+            tr: 1.5,
+            nshots,
+            ..FseParams::new(16, 4.0, 2.0, 0.01, PI, PI / 2.0)
+        }
     }
 
-    if params.debug_print {
-        println!("Signal: {:?}", signal);
-        println!("{:}", epg::vec::to_mxy(&epg));
-        println!("{:}", epg::vec::to_mz(&epg));
+    #[test]
+    fn test_restore_returns_last_echo() {
+        // an ideal restore after the first shot stores the last echo amplitude in Mz
+        let last_echo = simulate(fluid(true, 1))[15].norm();
+        let et1 = (-(1.5 - 0.16) / 4.0_f64).exp();
+        let expected = last_echo * et1 + 1.0 - et1;
+        assert!((mz_at_next_shot(&fluid(true, 1)) - expected).abs() < 1e-9);
     }
 
-    return signal;
-}
\ No newline at end of file
+    #[test]
+    fn test_restore_raises_steady_state() {
+        let plain = simulate(fluid(false, 10));
+        let restored = simulate(fluid(true, 10));
+        assert!(restored[0].norm() > 1.5 * plain[0].norm());
+    }
+}
@@ -0,0 +1,201 @@
+use num_complex::Complex64;
+
+use std::f64::consts::PI;
+
+use super::common::EchoTrain;
+use super::{Event, Sequence};
+use crate::types::Compartment;
+
+/// Inversion pulse played at the start of each shot.
+#[derive(Clone)]
+pub enum Inversion {
+    /// Instantaneous 180 degree rotation.
+    Ideal,
+    /// Hard-pulse decomposition of a shaped pulse, as (flip angle, phase) pairs
+    /// applied back to back.
+    Shaped(Vec<(f64, f64)>),
+}
+
+#[derive(Clone)]
+pub struct IrFseParams {
+    pub etl: usize,
+    pub t1: f64,
+    pub t2: f64,
+    pub esp: f64,
+    pub refocus_angle: f64,
+    pub cpmg_phase: f64,
+    pub inversion: Inversion,
+    /// Time from the inversion pulse to the excitation pulse.
+    pub inversion_time: f64,
+    /// Inversion-to-inversion repetition time.
+    pub tr: f64,
+    /// Number of shots simulated. The last shot is reported.
+    pub nshots: usize,
+    pub debug_print: bool,
+}
+
+impl IrFseParams {
+    /// Free recovery period between the end of the echo train and the next inversion.
+    pub fn recovery_time(&self) -> f64 {
+        self.train().recovery_time()
+    }
+
+    fn train(&self) -> EchoTrain {
+        let prep = match &self.inversion {
+            Inversion::Ideal => vec![(PI, 0.0)],
+            Inversion::Shaped(pulses) => pulses.clone(),
+        };
+        EchoTrain {
+            etl: self.etl,
+            t1: self.t1,
+            t2: self.t2,
+            esp: self.esp,
+            refocus_angle: self.refocus_angle,
+            cpmg_phase: self.cpmg_phase,
+            restore: false,
+            tr: self.tr,
+            nshots: self.nshots,
+            prep,
+            prep_time: self.inversion_time,
+            precession: 0.0,
+        }
+    }
+}
+
+pub fn simulate(params: IrFseParams) -> Vec<Complex64> {
+    params.train().run(params.debug_print).echoes
+}
+
+/// Longitudinal magnetization just before the excitation pulse of the last shot.
+pub fn mz_at_excitation(params: &IrFseParams) -> f64 {
+    params.train().run(params.debug_print).mz_excitation
+}
+
+impl Sequence for IrFseParams {
+    fn name(&self) -> &'static str {
+        "IRFSE"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.train().validate()
+    }
+
+    fn events(&self) -> Vec<Event> {
+        self.train().timeline().into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        self.nshots as f64 * self.tr
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        EchoTrain {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.train()
+        }
+        .run(false)
+        .echoes
+    }
+}
+
+/// Inversion time that nulls the tissue in `params` at the excitation of the last of
+/// its `nshots` shots, found by bisection on the simulated Mz. This accounts for the
+/// echo train's effect on Mz and for incomplete recovery, unlike `T1 ln(2)`, and
+/// approaches the steady-state null as `nshots` grows; a single shot from equilibrium
+/// gives `T1 ln(2)`. Returns `None` if the tissue cannot be nulled at this TR.
+pub fn null_inversion_time(params: &IrFseParams) -> Option<f64> {
+    let mz_at = |ti: f64| {
+        let mut p = params.clone();
+        p.inversion_time = ti;
+        p.debug_print = false;
+        mz_at_excitation(&p)
+    };
+
+    let mut lo = 0.0;
+    let mut hi = params.tr - params.etl as f64 * params.esp;
+    if hi < 0.0 {
+        return None;
+    }
+
+    let mz_lo = mz_at(lo);
+    if mz_lo * mz_at(hi) > 0.0 {
+        return None;
+    }
+
+    for _ in 0..50 {
+        let mid = 0.5 * (lo + hi);
+        if mz_at(mid) * mz_lo > 0.0 {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    Some(0.5 * (lo + hi))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flair(tr: f64) -> IrFseParams {
+        IrFseParams {
+            etl: 16,
+            t1: 4.0,
+            t2: 2.0,
+            esp: 0.01,
+            refocus_angle: PI,
+            cpmg_phase: PI / 2.0,
+            inversion: Inversion::Ideal,
+            inversion_time: 2.5,
+            tr,
+            nshots: 6,
+            debug_print: false,
+        }
+    }
+
+    #[test]
+    fn test_null_long_tr_matches_ln2() {
+        let ti = null_inversion_time(&flair(40.0)).unwrap();
+        assert!((ti - 4.0 * 2.0_f64.ln()).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_null_short_tr_nulls_signal() {
+        let mut p = flair(9.0);
+        let ti = null_inversion_time(&p).unwrap();
+        assert!(ti < 4.0 * 2.0_f64.ln());
+
+        p.inversion_time = ti;
+        assert!(mz_at_excitation(&p).abs() < 1e-6);
+        assert!(simulate(p)[0].norm() < 1e-6);
+    }
+
+    #[test]
+    fn test_null_depends_on_shots() {
+        let mut p = flair(9.0);
+        p.nshots = 1;
+        let single = null_inversion_time(&p).unwrap();
+        assert!((single - 4.0 * 2.0_f64.ln()).abs() < 1e-9);
+
+        p.nshots = 6;
+        let six = null_inversion_time(&p).unwrap();
+        assert!(six < single - 0.1);
+        p.inversion_time = six;
+        assert!(mz_at_excitation(&p).abs() < 1e-9);
+
+        // incomplete recovery leaves Mz of the sixth shot positive at the first null
+        p.inversion_time = single;
+        assert!(mz_at_excitation(&p) > 0.1);
+    }
+
+    #[test]
+    fn test_shaped_matches_ideal() {
+        let mut p = flair(9.0);
+        let ideal = simulate(p.clone());
+        p.inversion = Inversion::Shaped(vec![(PI / 2.0, 0.0), (PI / 2.0, 0.0)]);
+        let shaped = simulate(p);
+        assert!((ideal[3] - shaped[3]).norm() < 1e-9);
+    }
+}
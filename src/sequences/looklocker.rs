@@ -0,0 +1,218 @@
+use num_complex::Complex64;
+
+use super::common::{
+    invert, relaxation_factors, spoiled_gre_train, RfSpoiler, Timeline, RF_SPOIL_INCREMENT,
+};
+use super::{Event, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, EPG},
+};
+
+/// Three parameter fit `S(t) = A - B exp(-t / T1*)` of an inversion recovery.
+pub struct T1Fit {
+    pub a: f64,
+    pub b: f64,
+    /// Apparent T1 under the readout.
+    pub t1_star: f64,
+    /// Look-Locker corrected T1, `T1* (B / A - 1)`.
+    pub t1: f64,
+}
+
+/// Least squares fit of `A - B exp(-t / T1*)`. A and B are solved linearly for each
+/// candidate T1*, which is found by a log-spaced grid search and golden section refinement.
+pub fn fit(times: &[f64], signal: &[f64]) -> T1Fit {
+    assert_eq!(times.len(), signal.len(), "times and signal lengths differ");
+    assert!(times.len() >= 3, "at least three samples are needed");
+
+    let linear = |t1_star: f64| {
+        let e: Vec<f64> = times.iter().map(|t| (-t / t1_star).exp()).collect();
+        let n = times.len() as f64;
+        let (se, see) = (e.iter().sum::<f64>(), e.iter().map(|x| x * x).sum::<f64>());
+        let sy = signal.iter().sum::<f64>();
+        let sey = e.iter().zip(signal).map(|(x, y)| x * y).sum::<f64>();
+
+        // normal equations for y = a + c * e, with c = -B
+        let det = n * see - se * se;
+        let a = (see * sy - se * sey) / det;
+        let c = (n * sey - se * sy) / det;
+        let sse = e
+            .iter()
+            .zip(signal)
+            .map(|(x, y)| (a + c * x - y).powi(2))
+            .sum::<f64>();
+        (a, -c, sse)
+    };
+
+    let grid: Vec<f64> = (0..=200)
+        .map(|ix| 1e-3 * 1e4_f64.powf(ix as f64 / 200.0))
+        .collect();
+    let best = (0..grid.len())
+        .min_by(|&i, &j| linear(grid[i]).2.total_cmp(&linear(grid[j]).2))
+        .unwrap();
+
+    let mut lo = grid[best.saturating_sub(1)].ln();
+    let mut hi = grid[(best + 1).min(grid.len() - 1)].ln();
+    let ratio = (5.0_f64.sqrt() - 1.0) / 2.0;
+    for _ in 0..60 {
+        let x1 = hi - ratio * (hi - lo);
+        let x2 = lo + ratio * (hi - lo);
+        if linear(x1.exp()).2 < linear(x2.exp()).2 {
+            hi = x2;
+        } else {
+            lo = x1;
+        }
+    }
+
+    let t1_star = (0.5 * (lo + hi)).exp();
+    let (a, b, _) = linear(t1_star);
+
+    T1Fit {
+        a,
+        b,
+        t1_star,
+        t1: t1_star * (b / a - 1.0),
+    }
+}
+
+#[derive(Clone)]
+pub struct LookLockerParams {
+    pub t1: f64,
+    pub t2: f64,
+    pub flip_angle: f64,
+    /// Spacing of the continuous spoiled gradient echo readouts.
+    pub tr: f64,
+    /// Heart rate in beats per minute. The inversion is triggered on an R wave.
+    pub heart_rate: f64,
+    /// Length of the readout window after the inversion, in heartbeats.
+    pub nbeats: usize,
+    pub debug_print: bool,
+}
+
+impl LookLockerParams {
+    /// Duration of one cardiac cycle.
+    pub fn rr_interval(&self) -> f64 {
+        60.0 / self.heart_rate
+    }
+
+    pub fn nreads(&self) -> usize {
+        (self.nbeats as f64 * self.rr_interval() / self.tr).floor() as usize
+    }
+}
+
+pub struct LookLockerSignal {
+    /// Time of each readout after the inversion.
+    pub times: Vec<f64>,
+    pub signal: Vec<Complex64>,
+    pub fit: T1Fit,
+}
+
+pub fn simulate(params: LookLockerParams) -> LookLockerSignal {
+    let nreads = params.nreads();
+
+    let mut epg = epg::vec::EPGVecRepresentation::new(nreads + 1);
+    let mut spoiler = RfSpoiler::new(RF_SPOIL_INCREMENT);
+    let (et1d, et2d) = relaxation_factors(params.tr, params.t1, params.t2);
+
+    invert(&mut epg);
+    epg.delay(et1d, et2d);
+
+    let signal = spoiled_gre_train(
+        &mut epg,
+        params.flip_angle,
+        params.tr,
+        nreads,
+        params.t1,
+        params.t2,
+        &mut spoiler,
+    );
+    let times: Vec<f64> = (1..=nreads).map(|ix| ix as f64 * params.tr).collect();
+
+    let real: Vec<f64> = signal.iter().map(|s| s.re).collect();
+    let fit = fit(&times, &real);
+
+    if params.debug_print {
+        println!("Signal: {:?}", signal);
+        println!("T1* {:.4} T1 {:.4}", fit.t1_star, fit.t1);
+        println!("{:}", epg::vec::to_mxy(&epg));
+        println!("{:}", epg::vec::to_mz(&epg));
+    }
+
+    LookLockerSignal {
+        times,
+        signal,
+        fit,
+    }
+}
+
+impl Sequence for LookLockerParams {
+    fn name(&self) -> &'static str {
+        "LookLocker"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.tr <= 0.0 || self.heart_rate <= 0.0 {
+            return Err("TR and heart rate must be positive".into());
+        }
+        if self.nreads() < 3 {
+            return Err("readout window too short for a three parameter fit".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let nreads = self.nreads();
+        let mut timeline = Timeline::new();
+        let mut spoiler = RfSpoiler::new(RF_SPOIL_INCREMENT);
+        timeline.invert();
+        timeline.delay(self.tr);
+        timeline.spoiled_gre_train(self.flip_angle, self.tr, nreads, &mut spoiler, 0..nreads);
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        (self.nreads() + 1) as f64 * self.tr
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        simulate(LookLockerParams {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.clone()
+        })
+        .signal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tissues::{get_tissue, FieldStrength};
+    use crate::types::Tissue;
+
+    #[test]
+    fn test_fit_exact_recovery() {
+        let times: Vec<f64> = (1..12).map(|ix| 0.1 * ix as f64).collect();
+        let signal: Vec<f64> = times.iter().map(|t| 0.8 - 1.9 * (-t / 0.7).exp()).collect();
+        let res = fit(&times, &signal);
+        assert!((res.t1_star - 0.7).abs() < 1e-6);
+        assert!((res.a - 0.8).abs() < 1e-6);
+        assert!((res.b - 1.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_corrected_t1() {
+        let blood = get_tissue(Tissue::Blood, FieldStrength::T1_5);
+        let res = simulate(LookLockerParams {
+            t1: blood.t1,
+            t2: blood.t2,
+            flip_angle: 5.0_f64.to_radians(),
+            tr: 0.005,
+            heart_rate: 60.0,
+            nbeats: 4,
+            debug_print: false,
+        });
+        assert!(res.fit.t1_star < blood.t1);
+        assert!((res.fit.t1 - blood.t1).abs() / blood.t1 < 0.03);
+    }
+}
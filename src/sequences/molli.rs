@@ -0,0 +1,253 @@
+use num_complex::Complex64;
+
+use std::f64::consts::PI;
+
+use super::common::{balanced_ssfp_train, relaxation_factors, Timeline};
+use super::looklocker::{fit, T1Fit};
+use super::{Event, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, EPG},
+};
+
+#[derive(Clone)]
+pub struct MolliParams {
+    pub t1: f64,
+    pub t2: f64,
+    /// Flip angle of the bSSFP single-shot readouts.
+    pub flip_angle: f64,
+    /// Repetition time of the bSSFP readouts.
+    pub tr: f64,
+    /// Pulses per single-shot image. The middle one samples the k-space centre.
+    pub nreads: usize,
+    /// Heart rate in beats per minute.
+    pub heart_rate: f64,
+    /// Delay from the R wave to the k-space centre of each image.
+    pub trigger_delay: f64,
+    /// Images acquired after each inversion, e.g. `[5, 3]` for 5(3)3.
+    pub images_per_inversion: Vec<usize>,
+    /// Heartbeats without acquisition between inversion blocks.
+    pub recovery_beats: usize,
+    /// Inversion time of the first image after the first inversion.
+    pub first_ti: f64,
+    /// Increase of the first inversion time for every following inversion.
+    pub ti_increment: f64,
+    pub debug_print: bool,
+}
+
+impl MolliParams {
+    /// The 5(3)3 scheme with the usual 80 ms TI increment.
+    pub fn scheme_5_3_3(t1: f64, t2: f64, heart_rate: f64) -> Self {
+        Self {
+            t1,
+            t2,
+            flip_angle: 35.0 * PI / 180.0,
+            tr: 0.0028,
+            nreads: 64,
+            heart_rate,
+            trigger_delay: 0.5 * 60.0 / heart_rate,
+            images_per_inversion: vec![5, 3],
+            recovery_beats: 3,
+            first_ti: 0.1,
+            ti_increment: 0.08,
+            debug_print: false,
+        }
+    }
+
+    /// Duration of one cardiac cycle.
+    pub fn rr_interval(&self) -> f64 {
+        60.0 / self.heart_rate
+    }
+}
+
+pub struct MolliSignal {
+    /// Inversion time of each image, measured from its own inversion.
+    pub inversion_times: Vec<f64>,
+    /// k-space centre signal of each image.
+    pub signal: Vec<Complex64>,
+    pub fit: T1Fit,
+}
+
+enum Step {
+    Inversion,
+    Image,
+}
+
+impl MolliParams {
+    /// Start time of each inversion and single shot image from the first R wave, and
+    /// the inversion time of each image.
+    fn schedule(&self) -> (Vec<(f64, Step)>, Vec<f64>) {
+        let rr = self.rr_interval();
+        // the single shot starts with its alpha/2 pulse, and its centre echo is read
+        // (center + 1) TRs later.
+        let lead_in = (self.nreads / 2 + 1) as f64 * self.tr;
+
+        let mut steps: Vec<(f64, Step)> = Vec::new();
+        let mut inversion_times: Vec<f64> = Vec::new();
+        let mut beat = 0;
+        for (block, &nimages) in self.images_per_inversion.iter().enumerate() {
+            let ti = self.first_ti + block as f64 * self.ti_increment;
+            let first_center = beat as f64 * rr + self.trigger_delay;
+            steps.push((first_center - ti, Step::Inversion));
+            for image in 0..nimages {
+                let image_center = (beat + image) as f64 * rr + self.trigger_delay;
+                steps.push((image_center - lead_in, Step::Image));
+                inversion_times.push(image_center - (first_center - ti));
+            }
+            beat += nimages + self.recovery_beats;
+        }
+        (steps, inversion_times)
+    }
+
+    fn shot_duration(&self) -> f64 {
+        (self.nreads as f64 + 0.5) * self.tr
+    }
+
+    /// Time at which each step ends.
+    fn step_end(&self, time: f64, step: &Step) -> f64 {
+        match step {
+            Step::Inversion => time,
+            Step::Image => time + self.shot_duration(),
+        }
+    }
+}
+
+pub fn simulate(params: MolliParams) -> MolliSignal {
+    let center = params.nreads / 2;
+    let (steps, inversion_times) = params.schedule();
+
+    let mut epg = epg::vec::EPGVecRepresentation::new(3);
+    let mut signal: Vec<Complex64> = Vec::with_capacity(inversion_times.len());
+    let inversion = epg::common::gen_rotation_matrix(PI, 0.0);
+
+    // start from equilibrium at the earliest step
+    let mut now = steps[0].0;
+    for (time, step) in steps.iter() {
+        assert!(
+            *time >= now - 1e-12,
+            "steps overlap, check TI, trigger delay and readout length"
+        );
+        let (et1d, et2d) = relaxation_factors(time - now, params.t1, params.t2);
+        epg.delay(et1d, et2d);
+
+        match step {
+            Step::Inversion => {
+                epg.rotate(&inversion);
+                epg.crush();
+            }
+            Step::Image => {
+                let shot = balanced_ssfp_train(
+                    &mut epg,
+                    params.flip_angle,
+                    params.tr,
+                    params.nreads,
+                    params.t1,
+                    params.t2,
+                );
+                signal.push(shot[center]);
+                epg.crush();
+            }
+        }
+        now = params.step_end(*time, step);
+    }
+
+    let real: Vec<f64> = signal.iter().map(|s| s.re).collect();
+    let fit = fit(&inversion_times, &real);
+
+    if params.debug_print {
+        println!("Signal: {:?}", signal);
+        println!("T1* {:.4} T1 {:.4}", fit.t1_star, fit.t1);
+        println!("{:}", epg::vec::to_mxy(&epg));
+        println!("{:}", epg::vec::to_mz(&epg));
+    }
+
+    MolliSignal {
+        inversion_times,
+        signal,
+        fit,
+    }
+}
+
+/// Reports the k-space centre of each image.
+impl Sequence for MolliParams {
+    fn name(&self) -> &'static str {
+        "MOLLI"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.nreads == 0 || self.tr <= 0.0 || self.heart_rate <= 0.0 {
+            return Err("readouts, TR and heart rate must be positive".into());
+        }
+        let (steps, inversion_times) = self.schedule();
+        if inversion_times.len() < 3 {
+            return Err("at least three images are needed for the fit".into());
+        }
+        let mut now = steps[0].0;
+        for (time, step) in steps.iter() {
+            if *time < now - 1e-12 {
+                return Err("steps overlap, check TI, trigger delay and readout length".into());
+            }
+            now = self.step_end(*time, step);
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let center = self.nreads / 2;
+        let reads = center..center + 1;
+        let (steps, _) = self.schedule();
+        let start = steps[0].0;
+
+        let mut timeline = Timeline::new();
+        for (time, step) in steps.iter() {
+            timeline.delay(time - start - timeline.now());
+            match step {
+                Step::Inversion => timeline.pulse(PI, 0.0),
+                Step::Image => {
+                    timeline.balanced_ssfp_train(self.flip_angle, self.tr, self.nreads, reads.clone())
+                }
+            }
+            timeline.crush();
+        }
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        let (steps, _) = self.schedule();
+        let (time, step) = steps.last().unwrap();
+        self.step_end(*time, step) - steps[0].0
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        simulate(MolliParams {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.clone()
+        })
+        .signal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tissues::{get_tissue, FieldStrength};
+    use crate::types::Tissue;
+
+    #[test]
+    fn test_myocardium_t1() {
+        let res = simulate(MolliParams::scheme_5_3_3(1.0, 0.045, 60.0));
+        assert_eq!(res.signal.len(), 8);
+        assert!(res.signal[0].re < 0.0);
+        assert!((res.fit.t1 - 1.0).abs() / 1.0 < 0.1);
+    }
+
+    #[test]
+    fn test_heart_rate_dependence() {
+        // MOLLI underestimates long T1 more at high heart rates.
+        let blood = get_tissue(Tissue::Blood, FieldStrength::T1_5);
+        let slow = simulate(MolliParams::scheme_5_3_3(blood.t1, blood.t2, 50.0));
+        let fast = simulate(MolliParams::scheme_5_3_3(blood.t1, blood.t2, 100.0));
+        assert!(fast.fit.t1 < slow.fit.t1);
+    }
+}
@@ -0,0 +1,285 @@
+use num_complex::Complex64;
+
+use super::common::{
+    invert, relaxation_factors, spoiled_gre_train, RfSpoiler, Timeline, RF_SPOIL_INCREMENT,
+};
+use super::mprage::ViewOrder;
+use super::{Event, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, EPG},
+};
+
+#[derive(Clone)]
+pub struct Mp2rageParams {
+    pub t1: f64,
+    pub t2: f64,
+    /// Time from the inversion pulse to the k-space centre of the first GRE block.
+    pub inversion_time_1: f64,
+    /// Time from the inversion pulse to the k-space centre of the second GRE block.
+    pub inversion_time_2: f64,
+    pub flip_angle_1: f64,
+    pub flip_angle_2: f64,
+    /// Spacing of the gradient echo readouts.
+    pub esp: f64,
+    /// Number of readouts per GRE block.
+    pub nreads: usize,
+    /// Inversion-to-inversion repetition time.
+    pub mp2rage_tr: f64,
+    /// Relative transmit field. Scales both readout flip angles, the inversion is
+    /// assumed adiabatic.
+    pub b1: f64,
+    /// Number of shots simulated. The last shot is reported.
+    pub nshots: usize,
+    pub view_order: ViewOrder,
+    pub debug_print: bool,
+}
+
+impl Mp2rageParams {
+    /// Free delays (TA, TB, TC) between inversion, GRE blocks and the next inversion.
+    pub fn delays(&self) -> (f64, f64, f64) {
+        let center = self.view_order.center_index(self.nreads) as f64 * self.esp;
+        let train = self.nreads as f64 * self.esp;
+        let ta = self.inversion_time_1 - center;
+        let tb = self.inversion_time_2 - self.inversion_time_1 - train;
+        let tc = self.mp2rage_tr - self.inversion_time_2 - (train - center);
+        (ta, tb, tc)
+    }
+}
+
+pub struct Mp2rageSignal {
+    /// Readouts of the first GRE block of the last shot.
+    pub gre1: Vec<Complex64>,
+    /// Readouts of the second GRE block of the last shot.
+    pub gre2: Vec<Complex64>,
+    /// k-space centre signal of the first GRE block.
+    pub center1: Complex64,
+    /// k-space centre signal of the second GRE block.
+    pub center2: Complex64,
+    /// Combined UNI signal, GRE1 * conj(GRE2) / (|GRE1|^2 + |GRE2|^2).
+    pub uni: Complex64,
+}
+
+/// UNI combination of two complex GRE signals. The real part lies in [-0.5, 0.5].
+pub fn uni(gre1: Complex64, gre2: Complex64) -> Complex64 {
+    let denom = gre1.norm_sqr() + gre2.norm_sqr();
+    if denom == 0.0 {
+        return Complex64::from(0.0);
+    }
+    gre1 * gre2.conj() / denom
+}
+
+pub fn simulate(params: Mp2rageParams) -> Mp2rageSignal {
+    let nreads = params.nreads;
+    let (ta, tb, tc) = params.delays();
+
+    assert!(ta >= 0.0, "TI1 too short for the readouts before the k-space centre");
+    assert!(tb >= 0.0, "TI2 - TI1 too short for the first GRE block");
+    assert!(tc >= 0.0, "MP2RAGE TR too short for the second GRE block");
+    assert!(params.nshots > 0, "at least one shot must be simulated");
+
+    let mut epg = epg::vec::EPGVecRepresentation::new(2 * nreads + 1);
+    let mut spoiler = RfSpoiler::new(RF_SPOIL_INCREMENT);
+    let mut gre1: Vec<Complex64> = Vec::with_capacity(nreads);
+    let mut gre2: Vec<Complex64> = Vec::with_capacity(nreads);
+
+    let (et1_ta, et2_ta) = relaxation_factors(ta, params.t1, params.t2);
+    let (et1_tb, et2_tb) = relaxation_factors(tb, params.t1, params.t2);
+    let (et1_tc, et2_tc) = relaxation_factors(tc, params.t1, params.t2);
+
+    for _ in 0..params.nshots {
+        invert(&mut epg);
+        epg.delay(et1_ta, et2_ta);
+
+        gre1 = spoiled_gre_train(
+            &mut epg,
+            params.b1 * params.flip_angle_1,
+            params.esp,
+            nreads,
+            params.t1,
+            params.t2,
+            &mut spoiler,
+        );
+
+        epg.delay(et1_tb, et2_tb);
+
+        gre2 = spoiled_gre_train(
+            &mut epg,
+            params.b1 * params.flip_angle_2,
+            params.esp,
+            nreads,
+            params.t1,
+            params.t2,
+            &mut spoiler,
+        );
+
+        epg.delay(et1_tc, et2_tc);
+    }
+
+    let center_index = params.view_order.center_index(nreads);
+    let center1 = gre1[center_index];
+    let center2 = gre2[center_index];
+
+    if params.debug_print {
+        println!("GRE1: {:?}", gre1);
+        println!("GRE2: {:?}", gre2);
+        println!("{:}", epg::vec::to_mxy(&epg));
+        println!("{:}", epg::vec::to_mz(&epg));
+    }
+
+    Mp2rageSignal {
+        gre1,
+        gre2,
+        center1,
+        center2,
+        uni: uni(center1, center2),
+    }
+}
+
+/// Reports the readouts of both GRE blocks of the last shot, GRE1 first.
+impl Sequence for Mp2rageParams {
+    fn name(&self) -> &'static str {
+        "MP2RAGE"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let (ta, tb, tc) = self.delays();
+        if self.nreads == 0 {
+            return Err("at least one readout per GRE block".into());
+        }
+        if ta < 0.0 {
+            return Err("TI1 too short for the readouts before the k-space centre".into());
+        }
+        if tb < 0.0 {
+            return Err("TI2 - TI1 too short for the first GRE block".into());
+        }
+        if tc < 0.0 {
+            return Err("MP2RAGE TR too short for the second GRE block".into());
+        }
+        if self.nshots == 0 {
+            return Err("at least one shot must be simulated".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let (ta, tb, tc) = self.delays();
+        let mut timeline = Timeline::new();
+        let mut spoiler = RfSpoiler::new(RF_SPOIL_INCREMENT);
+        for shot in 0..self.nshots {
+            timeline.record = shot + 1 == self.nshots;
+            timeline.invert();
+            timeline.delay(ta);
+            for (flip_angle, delay) in [(self.flip_angle_1, tb), (self.flip_angle_2, tc)] {
+                timeline.spoiled_gre_train(
+                    self.b1 * flip_angle,
+                    self.esp,
+                    self.nreads,
+                    &mut spoiler,
+                    0..self.nreads,
+                );
+                timeline.delay(delay);
+            }
+        }
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        self.nshots as f64 * self.mp2rage_tr
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        let res = simulate(Mp2rageParams {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.clone()
+        });
+        [res.gre1, res.gre2].concat()
+    }
+}
+
+/// Tabulated real UNI signal against T1, for converting UNI images to T1 maps.
+pub struct Mp2rageLut {
+    pub t1: Vec<f64>,
+    pub uni: Vec<f64>,
+}
+
+impl Mp2rageLut {
+    /// Interpolate the T1 matching a UNI value. Returns `None` if the value is
+    /// outside the range covered by the table.
+    pub fn t1_from_uni(&self, uni: f64) -> Option<f64> {
+        self.t1
+            .windows(2)
+            .zip(self.uni.windows(2))
+            .find(|(_, u)| (u[0] - uni) * (u[1] - uni) <= 0.0 && u[0] != u[1])
+            .map(|(t, u)| t[0] + (uni - u[0]) * (t[1] - t[0]) / (u[1] - u[0]))
+    }
+}
+
+/// Build a UNI-to-T1 lookup table for the protocol in `params` over `t1_values`.
+/// `b1` overrides the relative transmit field of `params` when given.
+pub fn lookup_table(params: &Mp2rageParams, t1_values: &[f64], b1: Option<f64>) -> Mp2rageLut {
+    let uni = t1_values
+        .iter()
+        .map(|&t1| {
+            let mut p = params.clone();
+            p.t1 = t1;
+            p.debug_print = false;
+            if let Some(b1) = b1 {
+                p.b1 = b1;
+            }
+            simulate(p).uni.re
+        })
+        .collect();
+
+    Mp2rageLut {
+        t1: t1_values.to_vec(),
+        uni,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protocol() -> Mp2rageParams {
+        Mp2rageParams {
+            t1: 1.0,
+            t2: 0.08,
+            inversion_time_1: 0.7,
+            inversion_time_2: 2.5,
+            flip_angle_1: 4.0_f64.to_radians(),
+            flip_angle_2: 5.0_f64.to_radians(),
+            esp: 0.007,
+            nreads: 96,
+            mp2rage_tr: 5.0,
+            b1: 1.0,
+            nshots: 3,
+            view_order: ViewOrder::Linear,
+            debug_print: false,
+        }
+    }
+
+    #[test]
+    fn test_uni_bounds() {
+        for t1 in [0.3, 1.0, 2.0, 4.0] {
+            let mut p = protocol();
+            p.t1 = t1;
+            let res = simulate(p);
+            assert!(res.uni.re.abs() <= 0.5);
+        }
+    }
+
+    #[test]
+    fn test_lut_recovers_t1() {
+        let t1_values: Vec<f64> = (0..40).map(|ix| 0.2 + 0.1 * ix as f64).collect();
+        let lut = lookup_table(&protocol(), &t1_values, None);
+
+        let mut p = protocol();
+        p.t1 = 1.23;
+        let measured = simulate(p).uni.re;
+
+        let t1 = lut.t1_from_uni(measured).unwrap();
+        assert!((t1 - 1.23).abs() < 0.01);
+    }
+}
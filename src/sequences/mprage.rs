@@ -0,0 +1,208 @@
+use num_complex::Complex64;
+
+use super::common::{
+    invert, relaxation_factors, spoiled_gre_train, RfSpoiler, Timeline, RF_SPOIL_INCREMENT,
+};
+use super::{Event, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, EPG},
+};
+
+/// Order in which the readouts of a shot sample the partition direction of k-space.
+#[derive(Clone, Copy)]
+pub enum ViewOrder {
+    /// k-space centre is sampled by the middle readout of the train.
+    Linear,
+    /// k-space centre is sampled by the first readout of the train.
+    Centric,
+}
+
+impl ViewOrder {
+    pub fn center_index(&self, nreads: usize) -> usize {
+        match self {
+            ViewOrder::Linear => nreads / 2,
+            ViewOrder::Centric => 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MprageParams {
+    pub t1: f64,
+    pub t2: f64,
+    /// Time from the inversion pulse to the readout at the k-space centre.
+    pub inversion_time: f64,
+    pub flip_angle: f64,
+    /// Spacing of the gradient echo readouts.
+    pub esp: f64,
+    /// Number of readouts per shot.
+    pub nreads: usize,
+    /// Delay from the end of the readout train to the next inversion.
+    pub recovery_time: f64,
+    /// Number of shots simulated. The last shot is reported.
+    pub nshots: usize,
+    pub view_order: ViewOrder,
+    pub debug_print: bool,
+}
+
+impl MprageParams {
+    /// Time from the inversion pulse to the first readout of the train.
+    pub fn time_to_first_read(&self) -> f64 {
+        self.inversion_time - self.view_order.center_index(self.nreads) as f64 * self.esp
+    }
+
+    /// Inversion-to-inversion repetition time.
+    pub fn shot_tr(&self) -> f64 {
+        self.time_to_first_read() + self.nreads as f64 * self.esp + self.recovery_time
+    }
+}
+
+pub struct MprageSignal {
+    /// Signal of every readout of the last shot.
+    pub readouts: Vec<Complex64>,
+    /// Signal of the readout at the k-space centre.
+    pub center: Complex64,
+}
+
+pub fn simulate(params: MprageParams) -> MprageSignal {
+    let nreads = params.nreads;
+    let first_read = params.time_to_first_read();
+
+    assert!(
+        first_read >= 0.0,
+        "inversion time too short for the readouts before the k-space centre"
+    );
+    assert!(params.recovery_time >= 0.0, "recovery time must be positive");
+    assert!(params.nshots > 0, "at least one shot must be simulated");
+
+    let mut epg = epg::vec::EPGVecRepresentation::new(nreads + 1);
+    let mut spoiler = RfSpoiler::new(RF_SPOIL_INCREMENT);
+    let mut readouts: Vec<Complex64> = Vec::with_capacity(nreads);
+
+    let (et1_ti, et2_ti) = relaxation_factors(first_read, params.t1, params.t2);
+    let (et1_td, et2_td) = relaxation_factors(params.recovery_time, params.t1, params.t2);
+
+    for _ in 0..params.nshots {
+        invert(&mut epg);
+        epg.delay(et1_ti, et2_ti);
+
+        readouts = spoiled_gre_train(
+            &mut epg,
+            params.flip_angle,
+            params.esp,
+            nreads,
+            params.t1,
+            params.t2,
+            &mut spoiler,
+        );
+
+        epg.delay(et1_td, et2_td);
+    }
+
+    let center = readouts[params.view_order.center_index(nreads)];
+
+    if params.debug_print {
+        println!("Signal: {:?}", readouts);
+        println!("{:}", epg::vec::to_mxy(&epg));
+        println!("{:}", epg::vec::to_mz(&epg));
+    }
+
+    MprageSignal { readouts, center }
+}
+
+impl Sequence for MprageParams {
+    fn name(&self) -> &'static str {
+        "MPRAGE"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.nreads == 0 {
+            return Err("at least one readout per shot".into());
+        }
+        if self.time_to_first_read() < 0.0 {
+            return Err("inversion time too short for the readouts before the centre".into());
+        }
+        if self.recovery_time < 0.0 {
+            return Err("recovery time must be positive".into());
+        }
+        if self.nshots == 0 {
+            return Err("at least one shot must be simulated".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let mut timeline = Timeline::new();
+        let mut spoiler = RfSpoiler::new(RF_SPOIL_INCREMENT);
+        for shot in 0..self.nshots {
+            timeline.record = shot + 1 == self.nshots;
+            timeline.invert();
+            timeline.delay(self.time_to_first_read());
+            let reads = 0..self.nreads;
+            timeline.spoiled_gre_train(self.flip_angle, self.esp, self.nreads, &mut spoiler, reads);
+            timeline.delay(self.recovery_time);
+        }
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        self.nshots as f64 * self.shot_tr()
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        simulate(MprageParams {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.clone()
+        })
+        .readouts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tissues::{get_tissue, FieldStrength};
+    use crate::types::Tissue;
+
+    fn params(t1: f64, flip_angle: f64, nshots: usize) -> MprageParams {
+        MprageParams {
+            t1,
+            t2: 0.1,
+            inversion_time: 0.9,
+            flip_angle,
+            esp: 0.007,
+            nreads: 64,
+            recovery_time: 0.5,
+            nshots,
+            view_order: ViewOrder::Linear,
+            debug_print: false,
+        }
+    }
+
+    #[test]
+    fn test_small_flip_follows_inversion_recovery() {
+        let flip_angle = 1e-3;
+        let res = simulate(params(1.0, flip_angle, 1));
+
+        let expected = 1.0 - 2.0 * (-0.9_f64).exp();
+        let measured = res.center.re / flip_angle.sin();
+        assert!((measured - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_shot_tr() {
+        let p = params(1.0, 0.1, 1);
+        assert!((p.shot_tr() - (0.9 - 32.0 * 0.007 + 64.0 * 0.007 + 0.5)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_grey_white_contrast() {
+        let flip_angle = 8.0_f64.to_radians();
+        let t1 = |tissue| get_tissue(tissue, FieldStrength::T0_55).t1;
+        let wm = simulate(params(t1(Tissue::WhiteMatter), flip_angle, 4));
+        let gm = simulate(params(t1(Tissue::GreyMatter), flip_angle, 4));
+        assert!(wm.center.norm() > gm.center.norm());
+    }
+}
@@ -0,0 +1,238 @@
+use ndarray::{Array2, ArrayView1, Zip};
+use num_complex::{Complex, Complex64};
+
+use std::f64::consts::PI;
+
+use super::common::{invert, relaxation_factors, Timeline};
+use super::dephasing::Dephasing;
+use super::{Event, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, EPG},
+};
+
+/// FISP-type MR fingerprinting schedule. All per-TR arrays must have the same length.
+#[derive(Clone)]
+pub struct MrfParams {
+    pub t1: f64,
+    pub t2: f64,
+    pub flip_angles: Vec<f64>,
+    /// RF phase of each pulse, relative to the y axis. The receiver follows it.
+    pub phases: Vec<f64>,
+    pub tr: Vec<f64>,
+    pub te: Vec<f64>,
+    /// Reversible dephasing, attenuating each echo by its decay over TE.
+    pub dephasing: Dephasing,
+    /// Delay between an initial inversion and the first pulse, if any.
+    pub inversion_time: Option<f64>,
+    /// Number of EPG states retained. Higher dephasing orders are discarded.
+    pub n_states: usize,
+    pub debug_print: bool,
+}
+
+impl MrfParams {
+    pub fn ntr(&self) -> usize {
+        self.flip_angles.len()
+    }
+}
+
+fn fingerprint(params: &MrfParams, t1: f64, t2: f64, dephasing: &Dephasing) -> Vec<Complex64> {
+    let ntr = params.ntr();
+
+    assert!(
+        params.phases.len() == ntr && params.tr.len() == ntr && params.te.len() == ntr,
+        "flip angle, phase, TR and TE schedules must have the same length"
+    );
+
+    let mut epg = epg::vec::EPGVecRepresentation::new(params.n_states);
+    let mut signal: Vec<Complex64> = Vec::with_capacity(ntr);
+
+    if let Some(ti) = params.inversion_time {
+        let (et1d, et2d) = relaxation_factors(ti, t1, t2);
+        invert(&mut epg);
+        epg.delay(et1d, et2d);
+    }
+
+    for ix in 0..ntr {
+        let (et1_te, et2_te) = relaxation_factors(params.te[ix], t1, t2);
+        let (et1_tr, et2_tr) = relaxation_factors(params.tr[ix] - params.te[ix], t1, t2);
+
+        let phase = params.phases[ix];
+        let rf = epg::common::gen_rotation_matrix(params.flip_angles[ix], PI / 2.0 + phase);
+
+        epg.rotate(&rf);
+        epg.delay(et1_te, et2_te);
+        let decay = dephasing.decay(params.te[ix]);
+        signal.push(decay * epg.read() * (-Complex::i() * phase).exp());
+        // one unbalanced twist per TR
+        epg.grelax(et1_tr, et2_tr, 1);
+    }
+
+    if params.debug_print {
+        println!("Signal: {:?}", signal);
+        println!("{:}", epg::vec::to_mxy(&epg));
+        println!("{:}", epg::vec::to_mz(&epg));
+    }
+
+    signal
+}
+
+pub fn simulate(params: MrfParams) -> Vec<Complex64> {
+    fingerprint(&params, params.t1, params.t2, &params.dephasing)
+}
+
+impl Sequence for MrfParams {
+    fn name(&self) -> &'static str {
+        "MRF"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let ntr = self.ntr();
+        if ntr == 0 {
+            return Err("schedule needs at least one TR".into());
+        }
+        if self.phases.len() != ntr || self.tr.len() != ntr || self.te.len() != ntr {
+            return Err("flip angle, phase, TR and TE schedules must have the same length".into());
+        }
+        if self.te.iter().zip(self.tr.iter()).any(|(&te, &tr)| te < 0.0 || te > tr) {
+            return Err("TE must lie within its TR".into());
+        }
+        if self.inversion_time.is_some_and(|ti| ti < 0.0) {
+            return Err("inversion time must not be negative".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let mut timeline = Timeline::new();
+        if let Some(ti) = self.inversion_time {
+            timeline.invert();
+            timeline.delay(ti);
+        }
+        for ix in 0..self.ntr() {
+            let phase = self.phases[ix];
+            timeline.pulse(self.flip_angles[ix], PI / 2.0 + phase);
+            timeline.delay(self.te[ix]);
+            timeline.gradient_echo(self.te[ix], phase);
+            timeline.gradient(self.tr[ix] - self.te[ix], 1);
+        }
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        self.inversion_time.unwrap_or(0.0) + self.tr.iter().sum::<f64>()
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        fingerprint(self, pool.t1, pool.t2, &Dephasing::from_t2s(pool.t2, pool.t2s))
+    }
+}
+
+/// Simulate the fingerprint of every (t1, t2) entry, in parallel. The `t1` and `t2`
+/// of `params` are ignored, its dephasing applies to every entry. Rows of the result
+/// follow the order of `entries`.
+pub fn dictionary(params: &MrfParams, entries: &[(f64, f64)]) -> Array2<Complex64> {
+    let mut dict = Array2::zeros((entries.len(), params.ntr()));
+
+    Zip::from(dict.rows_mut())
+        .and(ArrayView1::from(entries))
+        .par_for_each(|mut row, &(t1, t2)| {
+            for (x, s) in row.iter_mut().zip(fingerprint(params, t1, t2, &params.dephasing)) {
+                *x = s;
+            }
+        });
+
+    dict
+}
+
+/// Index of the dictionary row with the largest normalized inner product with `signal`.
+pub fn match_fingerprint(dict: &Array2<Complex64>, signal: &[Complex64]) -> usize {
+    let signal_norm = signal.iter().map(|s| s.norm_sqr()).sum::<f64>().sqrt();
+
+    dict.rows()
+        .into_iter()
+        .map(|row| {
+            let inner: Complex64 = row.iter().zip(signal).map(|(d, s)| d.conj() * s).sum();
+            let row_norm = row.iter().map(|d| d.norm_sqr()).sum::<f64>().sqrt();
+            inner.norm() / (row_norm * signal_norm)
+        })
+        .enumerate()
+        .fold((0, f64::MIN), |best, (ix, score)| {
+            if score > best.1 {
+                (ix, score)
+            } else {
+                best
+            }
+        })
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(ntr: usize) -> MrfParams {
+        let flip_angles = (0..ntr)
+            .map(|ix| (10.0 + 50.0 * (PI * ix as f64 / 250.0).sin().abs()).to_radians())
+            .collect();
+        MrfParams {
+            t1: 1.0,
+            t2: 0.1,
+            flip_angles,
+            phases: vec![0.0; ntr],
+            tr: (0..ntr).map(|ix| 0.012 + 0.003 * (ix % 7) as f64 / 7.0).collect(),
+            te: vec![0.002; ntr],
+            dephasing: Dephasing::None,
+            inversion_time: Some(0.02),
+            n_states: 40,
+            debug_print: false,
+        }
+    }
+
+    #[test]
+    fn test_first_read_after_inversion() {
+        let mut p = schedule(10);
+        p.flip_angles[0] = PI / 2.0;
+        let res = simulate(p);
+
+        let expected = (1.0 - 2.0 * (-0.02_f64).exp()) * (-0.002_f64 / 0.1).exp();
+        assert!((res[0].re - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tissue_reads_with_t2_star() {
+        let mut p = schedule(10);
+        p.flip_angles[0] = PI / 2.0;
+        let pool = Compartment {
+            name: "test".into(),
+            fraction: 1.0,
+            t1: 1.0,
+            t2: 0.1,
+            t2s: 0.02,
+            adc: 0.0,
+        };
+        let signal = p.signal(&pool);
+        let expected = (1.0 - 2.0 * (-0.02_f64).exp()) * (-0.002_f64 / 0.02).exp();
+        assert!((signal[0].re - expected).abs() < 1e-9);
+
+        // the events apply the same decay
+        let played = crate::sequences::common::play_pool(&p.events(), &pool);
+        for (s, e) in played.iter().zip(signal.iter()) {
+            assert!((s - e).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_dictionary_match() {
+        let p = schedule(500);
+        let entries: Vec<(f64, f64)> = [0.4, 0.8, 1.2, 1.6]
+            .iter()
+            .flat_map(|&t1| [0.04, 0.08, 0.12].map(|t2| (t1, t2)))
+            .collect();
+        let dict = dictionary(&p, &entries);
+
+        let signal = fingerprint(&p, 1.2, 0.08, &Dephasing::None);
+        assert_eq!(entries[match_fingerprint(&dict, &signal)], (1.2, 0.08));
+        assert_eq!(dict.row(7).to_vec(), signal);
+    }
+}
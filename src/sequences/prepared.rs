@@ -0,0 +1,144 @@
+use num_complex::Complex64;
+
+use super::common::play_pool;
+use super::{Event, Sequence};
+use crate::types::{Compartment, TissueProperties};
+
+/// Magnetization preparation that can be played in front of a sequence.
+pub trait Preparation {
+    /// Check the parameters describe a playable preparation.
+    fn validate(&self) -> Result<(), String>;
+
+    /// Events of the preparation, starting at time zero. Readouts are not allowed.
+    fn events(&self) -> Vec<Event>;
+
+    /// Time from the start of the preparation to the start of the sequence.
+    fn duration(&self) -> f64;
+}
+
+/// `sequence` played once after `prep`, which acts on the magnetization seen by the
+/// first event of the sequence. The signal is found by playing the combined events,
+/// so the sequence must pass `Sequence::validate_events` for the tissue.
+#[derive(Clone)]
+pub struct Prepared<P, S> {
+    pub prep: P,
+    pub sequence: S,
+}
+
+impl<P: Preparation, S: Sequence> Sequence for Prepared<P, S> {
+    fn name(&self) -> &'static str {
+        self.sequence.name()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.prep.validate()?;
+        self.sequence.validate()
+    }
+
+    fn validate_tissue(&self, tissue: &TissueProperties) -> Result<(), String> {
+        self.sequence.validate_tissue(tissue)?;
+        self.sequence.validate_events(tissue)
+    }
+
+    fn validate_events(&self, tissue: &TissueProperties) -> Result<(), String> {
+        self.sequence.validate_events(tissue)
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let start = self.prep.duration();
+        let mut events = self.prep.events();
+        events.extend(self.sequence.events().iter().map(|e| e.delayed(start)));
+        events
+    }
+
+    fn duration(&self) -> f64 {
+        self.prep.duration() + self.sequence.duration()
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        play_pool(&self.events(), pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::dephasing::Dephasing;
+    use crate::sequences::dwse::{DiffusionGradient, DwSeParams};
+    use crate::sequences::gre::GreParams;
+    use crate::sequences::t2prep::{RefocusScheme, T2Prep};
+    use crate::sequences::Spoiling;
+    use crate::tissues::{get_tissue, FieldStrength};
+    use crate::types::Tissue;
+
+    fn t2_prepared_gre() -> Prepared<T2Prep, GreParams> {
+        Prepared {
+            prep: T2Prep {
+                duration: 0.05,
+                nrefocus: 4,
+                scheme: RefocusScheme::Mlev,
+                b1: 1.0,
+            },
+            sequence: GreParams {
+                t1: 1.0,
+                t2: 0.1,
+                flip_angle: 20.0_f64.to_radians(),
+                tr: 0.02,
+                echo_time: 0.01,
+                nreads: 20,
+                spoiling: Spoiling::Ideal,
+                dephasing: Dephasing::None,
+                debug_print: false,
+            },
+        }
+    }
+
+    #[test]
+    fn test_t2_prep_in_front_of_gre() {
+        let pool = Compartment {
+            name: "test".into(),
+            fraction: 1.0,
+            t1: 1e9,
+            t2: 0.05,
+            t2s: 0.02,
+            adc: 0.0,
+        };
+        let params = t2_prepared_gre();
+        let signal = params.signal(&pool);
+        assert_eq!(signal.len(), params.readout_times().len());
+
+        // prepared Mz of exp(-1), read after the echo time with T2*
+        let alpha = 20.0_f64.to_radians();
+        let expected = alpha.sin() * (-1.0_f64).exp() * (-0.01_f64 / 0.02).exp();
+        assert!((signal[0].norm() - expected).abs() < 1e-9);
+
+        // without T1 recovery the prepared train is the unprepared one scaled by exp(-1)
+        let unprepared = params.sequence.signal(&pool);
+        assert!((unprepared[5].norm() / signal[5].norm() - 1.0_f64.exp()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_prepared_needs_events() {
+        let tissue = get_tissue(Tissue::GreyMatter, FieldStrength::T3);
+        assert!(tissue.adc > 0.0);
+        assert!(t2_prepared_gre().simulate(&tissue).is_ok());
+
+        // diffusion weighting is not described by the events
+        let diffusion = Prepared {
+            prep: t2_prepared_gre().prep,
+            sequence: DwSeParams {
+                t1: 1.0,
+                t2: 0.1,
+                adc: 1e-9,
+                echo_time: 0.05,
+                gradient: DiffusionGradient {
+                    strength: 0.02,
+                    duration: 0.01,
+                    separation: 0.02,
+                },
+                debug_print: false,
+            },
+        };
+        assert!(diffusion.simulate(&tissue).is_err());
+    }
+}
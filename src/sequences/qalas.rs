@@ -0,0 +1,270 @@
+use num_complex::Complex64;
+
+use super::common::{invert, nelder_mead, relaxation_factors, Timeline};
+use super::t2prep::{Readout, T2Prep};
+use super::{Event, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, EPG},
+};
+
+/// QALAS cycle: T2 preparation, one readout block, inversion, four more readout
+/// blocks at a fixed spacing, then free recovery until the next cycle.
+#[derive(Clone)]
+pub struct QalasParams {
+    pub t1: f64,
+    pub t2: f64,
+    pub t2prep: T2Prep,
+    /// Readout train of every block. The middle readout samples the k-space centre.
+    pub readout: Readout,
+    /// Delay from the end of the first readout block to the inversion pulse.
+    pub inversion_delay: f64,
+    /// Delay from the inversion pulse to the start of the second readout block.
+    pub inversion_time: f64,
+    /// Start-to-start spacing of the four readout blocks after the inversion.
+    pub block_spacing: f64,
+    /// Cycle time from one T2 preparation to the next.
+    pub tr: f64,
+    /// Number of cycles simulated. The last cycle is reported.
+    pub nshots: usize,
+    pub debug_print: bool,
+}
+
+impl QalasParams {
+    /// Free recovery at the end of each cycle.
+    pub fn recovery_time(&self) -> f64 {
+        self.tr
+            - self.t2prep.duration
+            - self.readout.duration()
+            - self.inversion_delay
+            - self.inversion_time
+            - 3.0 * self.block_spacing
+            - self.readout.duration()
+    }
+}
+
+pub struct QalasSignal {
+    /// k-space centre signal of each of the five readout blocks.
+    pub blocks: Vec<Complex64>,
+    /// Every readout of each block.
+    pub readouts: Vec<Vec<Complex64>>,
+}
+
+pub struct QalasEstimate {
+    pub t1: f64,
+    pub t2: f64,
+    pub pd: f64,
+}
+
+fn run(params: &QalasParams, t1: f64, t2: f64) -> Vec<Vec<Complex64>> {
+    let recovery = params.recovery_time();
+    let gap = params.block_spacing - params.readout.duration();
+
+    assert!(gap >= 0.0, "readout blocks overlap, increase the block spacing");
+    assert!(recovery >= 0.0, "TR too short for the QALAS cycle");
+    assert!(params.nshots > 0, "at least one cycle must be simulated");
+
+    let mut epg = epg::vec::EPGVecRepresentation::new(params.readout.nreads() + 1);
+    let mut readouts: Vec<Vec<Complex64>> = Vec::with_capacity(5);
+
+    let (et1_inv, et2_inv) = relaxation_factors(params.inversion_delay, t1, t2);
+    let (et1_ti, et2_ti) = relaxation_factors(params.inversion_time, t1, t2);
+    let (et1_gap, et2_gap) = relaxation_factors(gap, t1, t2);
+    let (et1_tr, et2_tr) = relaxation_factors(recovery, t1, t2);
+
+    for _ in 0..params.nshots {
+        readouts.clear();
+
+        params.t2prep.apply(&mut epg, t1, t2);
+        readouts.push(params.readout.acquire(&mut epg, t1, t2));
+        epg.delay(et1_inv, et2_inv);
+
+        invert(&mut epg);
+        epg.delay(et1_ti, et2_ti);
+        for block in 0..4 {
+            if block > 0 {
+                epg.delay(et1_gap, et2_gap);
+            }
+            readouts.push(params.readout.acquire(&mut epg, t1, t2));
+        }
+
+        epg.crush();
+        epg.delay(et1_tr, et2_tr);
+    }
+
+    readouts
+}
+
+fn centers(params: &QalasParams, readouts: &[Vec<Complex64>]) -> Vec<Complex64> {
+    let center = params.readout.nreads() / 2;
+    readouts.iter().map(|r| r[center]).collect()
+}
+
+/// Signal with unit proton density.
+pub fn simulate(params: QalasParams) -> QalasSignal {
+    let readouts = run(&params, params.t1, params.t2);
+    let blocks = centers(&params, &readouts);
+
+    if params.debug_print {
+        println!("Blocks: {:?}", blocks);
+    }
+
+    QalasSignal { blocks, readouts }
+}
+
+/// Reports every readout of the five blocks of the last cycle.
+impl Sequence for QalasParams {
+    fn name(&self) -> &'static str {
+        "QALAS"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.t2prep.nrefocus == 0 {
+            return Err("T2 prep needs at least one refocusing pulse".into());
+        }
+        if self.readout.nreads() == 0 {
+            return Err("at least one readout per block".into());
+        }
+        if self.block_spacing < self.readout.duration() {
+            return Err("readout blocks overlap, increase the block spacing".into());
+        }
+        if self.recovery_time() < 0.0 {
+            return Err("TR too short for the QALAS cycle".into());
+        }
+        if self.nshots == 0 {
+            return Err("at least one cycle must be simulated".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let gap = self.block_spacing - self.readout.duration();
+        let mut timeline = Timeline::new();
+        for shot in 0..self.nshots {
+            timeline.record = shot + 1 == self.nshots;
+            self.t2prep.play(&mut timeline);
+            self.readout.play(&mut timeline);
+            timeline.delay(self.inversion_delay);
+
+            timeline.invert();
+            timeline.delay(self.inversion_time);
+            for block in 0..4 {
+                if block > 0 {
+                    timeline.delay(gap);
+                }
+                self.readout.play(&mut timeline);
+            }
+
+            timeline.crush();
+            timeline.delay(self.recovery_time());
+        }
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        self.nshots as f64 * self.tr
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        run(self, pool.t1, pool.t2).concat()
+    }
+}
+
+/// Joint T1, T2 and PD fit of the five block signals against the EPG model of the
+/// protocol in `params`, whose tissue values are ignored. PD is solved linearly, T1
+/// and T2 by a log-spaced grid search refined with Nelder-Mead.
+pub fn estimate(params: &QalasParams, blocks: &[Complex64]) -> QalasEstimate {
+    let measured: Vec<f64> = blocks.iter().map(|s| s.re).collect();
+
+    // (sse, pd) for the log relaxation times
+    let residual = |x: &[f64]| {
+        let (t1, t2) = (x[0].exp(), x[1].exp());
+        let model: Vec<f64> = centers(params, &run(params, t1, t2))
+            .iter()
+            .map(|s| s.re)
+            .collect();
+        let mm = model.iter().map(|m| m * m).sum::<f64>();
+        let my = model.iter().zip(&measured).map(|(m, y)| m * y).sum::<f64>();
+        let pd = my / mm;
+        let sse = model
+            .iter()
+            .zip(&measured)
+            .map(|(m, y)| (y - pd * m).powi(2))
+            .sum::<f64>();
+        (sse, pd)
+    };
+
+    let log_grid = |lo: f64, hi: f64, n: usize| -> Vec<f64> {
+        (0..n)
+            .map(|ix| lo.ln() + (hi / lo).ln() * ix as f64 / (n - 1) as f64)
+            .collect()
+    };
+
+    let mut start = [0.0, 0.0];
+    let mut best = f64::MAX;
+    for &lt1 in log_grid(0.1, 5.0, 16).iter() {
+        for &lt2 in log_grid(0.01, 2.0, 16).iter() {
+            let (sse, _) = residual(&[lt1, lt2]);
+            if sse < best {
+                best = sse;
+                start = [lt1, lt2];
+            }
+        }
+    }
+
+    let x = nelder_mead(|x| residual(x).0, &start, &[0.1, 0.1], 120);
+    let (_, pd) = residual(&x);
+
+    QalasEstimate {
+        t1: x[0].exp(),
+        t2: x[1].exp(),
+        pd,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::t2prep::RefocusScheme;
+    use crate::tissues::{get_tissue, FieldStrength};
+    use crate::types::{Tissue, TissueProperties};
+
+    #[test]
+    fn test_joint_estimate() {
+        let params = QalasParams {
+            t1: 0.9,
+            t2: 0.07,
+            t2prep: T2Prep {
+                duration: 0.1,
+                nrefocus: 4,
+                scheme: RefocusScheme::Mlev,
+                b1: 1.0,
+            },
+            readout: Readout::SpoiledGre {
+                flip_angle: 4.0_f64.to_radians(),
+                tr: 0.0023,
+                nreads: 32,
+            },
+            inversion_delay: 0.01,
+            inversion_time: 0.1,
+            block_spacing: 0.9,
+            tr: 4.5,
+            nshots: 3,
+            debug_print: false,
+        };
+
+        let tissue = TissueProperties {
+            pd: 0.8,
+            t1: 0.9,
+            t2: 0.07,
+            t2s: 0.05,
+            ..get_tissue(Tissue::WhiteMatter, FieldStrength::T3)
+        };
+        let readouts = params.simulate(&tissue).unwrap();
+        let blocks: Vec<Complex64> = readouts.chunks(32).map(|r| r[16]).collect();
+        let res = estimate(&params, &blocks);
+        assert!((res.t1 - 0.9).abs() / 0.9 < 0.01);
+        assert!((res.t2 - 0.07).abs() / 0.07 < 0.01);
+        assert!((res.pd - 0.8).abs() / 0.8 < 0.01);
+    }
+}
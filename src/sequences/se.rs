@@ -1,7 +1,14 @@
 use num_complex::Complex64;
 
-use crate::{epg, types::EPG};
+use std::f64::consts::PI;
 
+use super::{Event, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, EPG},
+};
+
+#[derive(Clone)]
 pub struct SeParams {
     pub t1: f64,
     pub t2: f64,
@@ -35,5 +42,61 @@ pub fn simulate(params: SeParams) -> Vec<Complex64> {
         println!("{:}", epg::vec::to_mz(&epg));
     }
 
-    return signal;
+    signal
+}
+
+impl Sequence for SeParams {
+    fn name(&self) -> &'static str {
+        "SE"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.echo_time <= 0.0 {
+            return Err("echo time must be positive".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let half = self.echo_time / 2.0;
+        vec![
+            Event::Pulse {
+                time: 0.0,
+                flip_angle: PI / 2.0,
+                phase: PI / 2.0,
+            },
+            Event::Gradient {
+                time: 0.0,
+                duration: half,
+                ntwists: 1,
+            },
+            Event::Pulse {
+                time: half,
+                flip_angle: self.refocus_angle,
+                phase: self.refocus_phase,
+            },
+            Event::Gradient {
+                time: half,
+                duration: half,
+                ntwists: 1,
+            },
+            Event::Readout {
+                time: self.echo_time,
+                phase: 0.0,
+                tau: 0.0,
+            },
+        ]
+    }
+
+    fn duration(&self) -> f64 {
+        self.echo_time
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        simulate(SeParams {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.clone()
+        })
+    }
 }
@@ -1,7 +1,10 @@
 use num_complex::Complex64;
 
-use crate::{epg, types::EPG};
+use super::common::EchoTrain;
+use super::{Event, Sequence};
+use crate::types::Compartment;
 
+#[derive(Clone)]
 pub struct SpaceParams {
     pub etl: usize,
     pub t1: f64,
@@ -9,40 +12,136 @@ pub struct SpaceParams {
     pub esp: f64,
     pub refocus_angle: f64,
     pub cpmg_phase: f64,
+    /// Play a -90 degree restore pulse, phased to the last echo, at the end of the train.
+    pub restore: bool,
+    /// Shot repetition time.
+    pub tr: f64,
+    /// Number of shots simulated. The last shot is reported.
+    pub nshots: usize,
     pub debug_print: bool,
 }
 
-pub fn simulate(params: SpaceParams) -> Vec<Complex64>{
-    
-    let etl = params.etl;
-
-    let mut epg = epg::vec::EPGVecRepresentation::new(etl / 2 + 1);
+impl SpaceParams {
+    /// Single shot without restore, TR set to the echo train length.
+    pub fn new(
+        etl: usize,
+        t1: f64,
+        t2: f64,
+        esp: f64,
+        refocus_angle: f64,
+        cpmg_phase: f64,
+    ) -> Self {
+        Self {
+            etl,
+            t1,
+            t2,
+            esp,
+            refocus_angle,
+            cpmg_phase,
+            restore: false,
+            tr: etl as f64 * esp,
+            nshots: 1,
+            debug_print: false,
+        }
+    }
 
-    let mut signal: Vec<Complex64> = Vec::with_capacity(etl + 1);
+    fn train(&self) -> EchoTrain {
+        EchoTrain {
+            etl: self.etl,
+            t1: self.t1,
+            t2: self.t2,
+            esp: self.esp,
+            refocus_angle: self.refocus_angle,
+            cpmg_phase: self.cpmg_phase,
+            restore: self.restore,
+            tr: self.tr,
+            nshots: self.nshots,
+            prep: vec![],
+            prep_time: 0.0,
+            precession: 0.0,
+        }
+    }
+}
 
-    let x180 = epg::common::gen_rotation_matrix(params.refocus_angle, params.cpmg_phase);
+pub fn simulate(params: SpaceParams) -> Vec<Complex64> {
+    params.train().run(params.debug_print).echoes
+}
 
-    // dt is the spacing of our events, unsed for dephasing/relaxation
-    let dt = params.esp / 2.0;
-    let et1d = Complex64::from((-dt / params.t1).exp());
-    let et2d = Complex64::from((-dt / params.t2).exp());
+/// Longitudinal magnetization available to the excitation of the shot after the last one.
+pub fn mz_at_next_shot(params: &SpaceParams) -> f64 {
+    params.train().run(params.debug_print).mz_next
+}
 
-    epg.excite();
+impl Sequence for SpaceParams {
+    fn name(&self) -> &'static str {
+        "SPACE"
+    }
 
-    for _ in 0..etl {
-        epg.grelax(et1d, et2d, 1);
-        epg.rotate(&x180);
-        epg.grelax(et1d, et2d, 1);
+    fn validate(&self) -> Result<(), String> {
+        self.train().validate()
+    }
 
-        signal.push(epg.read());
+    fn events(&self) -> Vec<Event> {
+        self.train().timeline().into_events()
     }
 
-    if params.debug_print {
-        println!("Signal: {:?}", signal);
-        println!("{:}", epg::vec::to_mxy(&epg));
-        println!("{:}", epg::vec::to_mz(&epg));
+    fn duration(&self) -> f64 {
+        self.nshots as f64 * self.tr
     }
 
-    return signal;
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        EchoTrain {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.train()
+        }
+        .run(false)
+        .echoes
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn csf(restore: bool, nshots: usize) -> SpaceParams {
+        // refocusing about x, along the excited magnetization, for a stable train
+        SpaceParams {
+            restore,
+            tr: 2.0,
+            nshots,
+            ..SpaceParams::new(64, 4.0, 2.0, 0.004, 120.0_f64.to_radians(), 0.0)
+        }
+    }
+
+    #[test]
+    fn test_single_shot_from_equilibrium() {
+        let signal = simulate(SpaceParams::new(8, 1.0, 0.1, 0.01, PI, PI / 2.0));
+        for (ix, s) in signal.iter().enumerate() {
+            let t = (ix + 1) as f64 * 0.01;
+            assert!((s.norm() - (-t / 0.1).exp()).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_steady_state_reached() {
+        let first = simulate(csf(false, 1));
+        let steady = simulate(csf(false, 20));
+        let next = simulate(csf(false, 21));
+        assert!((steady[31] - next[31]).norm() < 1e-6);
+        assert!(steady[31].norm() < 0.5 * first[31].norm());
+    }
+
+    #[test]
+    fn test_restore_raises_steady_state() {
+        let plain = simulate(csf(false, 20));
+        let restored = simulate(csf(true, 20));
+        assert!(restored[31].norm() > 1.5 * plain[31].norm());
+
+        // the restored echo recovers towards equilibrium over the rest of the TR
+        let et1 = (-(2.0 - 64.0 * 0.004) / 4.0_f64).exp();
+        let expected = restored[63].norm() * et1 + 1.0 - et1;
+        assert!((mz_at_next_shot(&csf(true, 20)) - expected).abs() < 1e-9);
+    }
+}
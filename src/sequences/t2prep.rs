@@ -0,0 +1,266 @@
+use num_complex::Complex64;
+
+use std::f64::consts::PI;
+
+use super::common::{
+    balanced_ssfp_train, relaxation_factors, spoiled_gre_train, RfSpoiler, Timeline,
+    RF_SPOIL_INCREMENT,
+};
+use super::{Event, Preparation, Sequence};
+use crate::{
+    epg,
+    types::{Compartment, EPG},
+};
+
+/// Phase cycling of the refocusing pulses inside a T2 preparation.
+#[derive(Clone, Copy)]
+pub enum RefocusScheme {
+    /// All refocusing pulses along the tipped-down magnetization, as in CPMG.
+    Cpmg,
+    /// MLEV-4 cycling (R R -R -R), more robust to B1 errors.
+    Mlev,
+}
+
+/// 90x - [180] x n - (-90x) composite followed by a crusher. Stores
+/// `exp(-duration / T2)` of the longitudinal magnetization.
+#[derive(Clone, Copy)]
+pub struct T2Prep {
+    /// Time from the tip-down to the tip-up pulse.
+    pub duration: f64,
+    /// Number of refocusing pulses.
+    pub nrefocus: usize,
+    pub scheme: RefocusScheme,
+    /// Relative transmit field, scales every pulse of the module.
+    pub b1: f64,
+}
+
+impl T2Prep {
+    fn refocus_phase(&self, ix: usize) -> f64 {
+        match self.scheme {
+            RefocusScheme::Cpmg => PI / 2.0,
+            RefocusScheme::Mlev if ix % 4 < 2 => PI / 2.0,
+            RefocusScheme::Mlev => -PI / 2.0,
+        }
+    }
+
+    pub(crate) fn apply<E: EPG>(&self, epg: &mut E, t1: f64, t2: f64) {
+        assert!(self.nrefocus > 0, "T2 prep needs at least one refocusing pulse");
+
+        let tau = self.duration / self.nrefocus as f64;
+        let (et1d, et2d) = relaxation_factors(tau / 2.0, t1, t2);
+
+        let tip_down = epg::common::gen_rotation_matrix(self.b1 * PI / 2.0, 0.0);
+        let tip_up = epg::common::gen_rotation_matrix(-self.b1 * PI / 2.0, 0.0);
+
+        epg.rotate(&tip_down);
+        for ix in 0..self.nrefocus {
+            let refocus = epg::common::gen_rotation_matrix(self.b1 * PI, self.refocus_phase(ix));
+
+            epg.delay(et1d, et2d);
+            epg.rotate(&refocus);
+            epg.delay(et1d, et2d);
+        }
+        epg.rotate(&tip_up);
+        epg.crush();
+    }
+
+    /// Events of `apply`.
+    pub(crate) fn play(&self, timeline: &mut Timeline) {
+        let tau = self.duration / self.nrefocus as f64;
+        timeline.pulse(self.b1 * PI / 2.0, 0.0);
+        for ix in 0..self.nrefocus {
+            timeline.delay(tau / 2.0);
+            timeline.pulse(self.b1 * PI, self.refocus_phase(ix));
+            timeline.delay(tau / 2.0);
+        }
+        timeline.pulse(-self.b1 * PI / 2.0, 0.0);
+        timeline.crush();
+    }
+}
+
+impl Preparation for T2Prep {
+    fn validate(&self) -> Result<(), String> {
+        if self.nrefocus == 0 {
+            return Err("T2 prep needs at least one refocusing pulse".into());
+        }
+        if self.duration <= 0.0 {
+            return Err("T2 prep duration must be positive".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let mut timeline = Timeline::new();
+        self.play(&mut timeline);
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        self.duration
+    }
+}
+
+/// Readout train played after a magnetization preparation.
+#[derive(Clone, Copy)]
+pub enum Readout {
+    /// RF-spoiled gradient echoes.
+    SpoiledGre { flip_angle: f64, tr: f64, nreads: usize },
+    /// Balanced SSFP with alpha/2 catalyzation.
+    BalancedSsfp { flip_angle: f64, tr: f64, nreads: usize },
+}
+
+impl Readout {
+    pub fn nreads(&self) -> usize {
+        match *self {
+            Readout::SpoiledGre { nreads, .. } | Readout::BalancedSsfp { nreads, .. } => nreads,
+        }
+    }
+
+    /// Time spanned by the train.
+    pub fn duration(&self) -> f64 {
+        match *self {
+            Readout::SpoiledGre { tr, nreads, .. } => nreads as f64 * tr,
+            Readout::BalancedSsfp { tr, nreads, .. } => (nreads as f64 + 0.5) * tr,
+        }
+    }
+
+    pub(crate) fn acquire<E: EPG>(&self, epg: &mut E, t1: f64, t2: f64) -> Vec<Complex64> {
+        match *self {
+            Readout::SpoiledGre {
+                flip_angle,
+                tr,
+                nreads,
+            } => {
+                let mut spoiler = RfSpoiler::new(RF_SPOIL_INCREMENT);
+                spoiled_gre_train(epg, flip_angle, tr, nreads, t1, t2, &mut spoiler)
+            }
+            Readout::BalancedSsfp {
+                flip_angle,
+                tr,
+                nreads,
+            } => balanced_ssfp_train(epg, flip_angle, tr, nreads, t1, t2),
+        }
+    }
+
+    /// Events of `acquire`.
+    pub(crate) fn play(&self, timeline: &mut Timeline) {
+        match *self {
+            Readout::SpoiledGre {
+                flip_angle,
+                tr,
+                nreads,
+            } => {
+                let mut spoiler = RfSpoiler::new(RF_SPOIL_INCREMENT);
+                timeline.spoiled_gre_train(flip_angle, tr, nreads, &mut spoiler, 0..nreads);
+            }
+            Readout::BalancedSsfp {
+                flip_angle,
+                tr,
+                nreads,
+            } => timeline.balanced_ssfp_train(flip_angle, tr, nreads, 0..nreads),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct T2PrepParams {
+    pub t1: f64,
+    pub t2: f64,
+    pub prep: T2Prep,
+    /// Delay from the end of the preparation to the first readout pulse.
+    pub delay: f64,
+    pub readout: Readout,
+    pub debug_print: bool,
+}
+
+pub fn simulate(params: T2PrepParams) -> Vec<Complex64> {
+    let mut epg = epg::vec::EPGVecRepresentation::new(params.readout.nreads() + 1);
+    let (et1d, et2d) = relaxation_factors(params.delay, params.t1, params.t2);
+
+    params.prep.apply(&mut epg, params.t1, params.t2);
+    epg.delay(et1d, et2d);
+    let signal = params.readout.acquire(&mut epg, params.t1, params.t2);
+
+    if params.debug_print {
+        println!("Signal: {:?}", signal);
+        println!("{:}", epg::vec::to_mxy(&epg));
+        println!("{:}", epg::vec::to_mz(&epg));
+    }
+
+    signal
+}
+
+impl Sequence for T2PrepParams {
+    fn name(&self) -> &'static str {
+        "T2Prep"
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.prep.validate()?;
+        if self.delay < 0.0 {
+            return Err("delay must not be negative".into());
+        }
+        if self.readout.nreads() == 0 {
+            return Err("at least one readout is needed".into());
+        }
+        Ok(())
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let mut timeline = Timeline::new();
+        self.prep.play(&mut timeline);
+        timeline.delay(self.delay);
+        self.readout.play(&mut timeline);
+        timeline.into_events()
+    }
+
+    fn duration(&self) -> f64 {
+        self.prep.duration + self.delay + self.readout.duration()
+    }
+
+    fn signal(&self, pool: &Compartment) -> Vec<Complex64> {
+        simulate(T2PrepParams {
+            t1: pool.t1,
+            t2: pool.t2,
+            ..self.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prepared_mz(prep: T2Prep) -> f64 {
+        let mut epg = epg::vec::EPGVecRepresentation::new(3);
+        prep.apply(&mut epg, 1e9, 0.05);
+        epg.read_mz().re
+    }
+
+    #[test]
+    fn test_stores_t2_decay() {
+        for scheme in [RefocusScheme::Cpmg, RefocusScheme::Mlev] {
+            let mz = prepared_mz(T2Prep {
+                duration: 0.05,
+                nrefocus: 4,
+                scheme,
+                b1: 1.0,
+            });
+            assert!((mz - (-1.0_f64).exp()).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_mlev_robust_to_b1() {
+        let prep = |scheme| T2Prep {
+            duration: 0.05,
+            nrefocus: 8,
+            scheme,
+            b1: 0.85,
+        };
+        let expected = (-1.0_f64).exp();
+        let cpmg = prepared_mz(prep(RefocusScheme::Cpmg));
+        let mlev = prepared_mz(prep(RefocusScheme::Mlev));
+        assert!((mlev - expected).abs() < (cpmg - expected).abs());
+    }
+}
@@ -1,53 +1,415 @@
-/// Some common tissue properties
-///
+//! Some common tissue properties
+//!
+//! Relaxation times in seconds, per field strength. Proton density is relative to CSF
+//! and does not depend on the field.
 
+use crate::types::{Tissue, TissueProperties};
+
+pub mod agent;
+pub mod library;
+
+/// Field strengths with a relaxation table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldStrength {
+    T0_55,
+    T1_5,
+    T3,
+    T7,
+}
+
+impl FieldStrength {
+    /// Every tabulated field strength, lowest first.
+    pub const ALL: [FieldStrength; 4] = [
+        FieldStrength::T0_55,
+        FieldStrength::T1_5,
+        FieldStrength::T3,
+        FieldStrength::T7,
+    ];
+
+    pub fn tesla(&self) -> f64 {
+        match self {
+            FieldStrength::T0_55 => 0.55,
+            FieldStrength::T1_5 => 1.5,
+            FieldStrength::T3 => 3.0,
+            FieldStrength::T7 => 7.0,
+        }
+    }
+
+    /// Tabulated field strength closest to `b0` (Tesla).
+    pub fn nearest(b0: f64) -> Self {
+        FieldStrength::ALL
+            .into_iter()
+            .min_by(|a, b| (a.tesla() - b0).abs().total_cmp(&(b.tesla() - b0).abs()))
+            .unwrap()
+    }
+}
+
+/// Properties of `tissue` at `field`.
+pub fn get_tissue(tissue: Tissue, field: FieldStrength) -> TissueProperties {
+    match field {
+        FieldStrength::T0_55 => tissuep5t::get_tissue(tissue),
+        FieldStrength::T1_5 => tissue1p5t::get_tissue(tissue),
+        FieldStrength::T3 => tissue3t::get_tissue(tissue),
+        FieldStrength::T7 => tissue7t::get_tissue(tissue),
+    }
+}
+
+/// 0.55T
 pub mod tissuep5t {
     use crate::types::{Tissue, TissueProperties};
 
     pub fn get_tissue(tissue: Tissue) -> TissueProperties {
         match tissue {
             Tissue::WhiteMatter => TissueProperties {
-                name: "wm",
+                name: "wm".into(),
                 pd: 0.69,
                 t1: 0.505,
                 t2: 0.089,
                 t2s: 0.07,
+                adc: 0.7e-9,
+                compartments: vec![],
+                exchange: vec![],
             },
             Tissue::GreyMatter => TissueProperties {
-                name: "gm",
+                name: "gm".into(),
                 pd: 0.82,
                 t1: 0.775,
                 t2: 0.110,
                 t2s: 0.09,
+                adc: 0.8e-9,
+                compartments: vec![],
+                exchange: vec![],
             },
+            // the 1.5T caudate scaled by the grey matter field dependence
             Tissue::Caudate => TissueProperties {
-                name: "caudate",
+                name: "caudate".into(),
                 pd: 0.82,
-                t1: 0.505,
-                t2: 0.089,
-                t2s: 0.09,
+                t1: 0.747,
+                t2: 0.088,
+                t2s: 0.08,
+                adc: 0.75e-9,
+                compartments: vec![],
+                exchange: vec![],
             },
             Tissue::Thalamus => TissueProperties {
-                name: "thalamus",
+                name: "thalamus".into(),
                 pd: 0.82,
                 t1: 0.730,
                 t2: 0.1,
                 t2s: 0.09,
+                adc: 0.75e-9,
+                compartments: vec![],
+                exchange: vec![],
             },
             Tissue::CerebroSpinalFluid => TissueProperties {
-                name: "csf",
+                name: "csf".into(),
                 pd: 1.0,
                 t1: 4.0,
                 t2: 2.0,
                 t2s: 0.2,
+                adc: 3.0e-9,
+                compartments: vec![],
+                exchange: vec![],
             },
             Tissue::Blood => TissueProperties {
-                name: "blood",
+                name: "blood".into(),
                 pd: 1.0,
                 t1: 1.12,
                 t2: 0.26,
                 t2s: 0.03,
+                adc: 1.6e-9,
+                compartments: vec![],
+                exchange: vec![],
             },
         }
     }
 }
+
+/// 1.5T
+pub mod tissue1p5t {
+    use crate::types::{Tissue, TissueProperties};
+
+    pub fn get_tissue(tissue: Tissue) -> TissueProperties {
+        match tissue {
+            Tissue::WhiteMatter => TissueProperties {
+                name: "wm".into(),
+                pd: 0.69,
+                t1: 0.884,
+                t2: 0.072,
+                t2s: 0.066,
+                adc: 0.7e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::GreyMatter => TissueProperties {
+                name: "gm".into(),
+                pd: 0.82,
+                t1: 1.124,
+                t2: 0.095,
+                t2s: 0.084,
+                adc: 0.8e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::Caudate => TissueProperties {
+                name: "caudate".into(),
+                pd: 0.82,
+                t1: 1.083,
+                t2: 0.076,
+                t2s: 0.07,
+                adc: 0.75e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::Thalamus => TissueProperties {
+                name: "thalamus".into(),
+                pd: 0.82,
+                t1: 0.981,
+                t2: 0.085,
+                t2s: 0.07,
+                adc: 0.75e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::CerebroSpinalFluid => TissueProperties {
+                name: "csf".into(),
+                pd: 1.0,
+                t1: 4.07,
+                t2: 2.0,
+                t2s: 0.2,
+                adc: 3.0e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::Blood => TissueProperties {
+                name: "blood".into(),
+                pd: 1.0,
+                t1: 1.441,
+                t2: 0.29,
+                t2s: 0.05,
+                adc: 1.6e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+        }
+    }
+}
+
+/// 3T
+pub mod tissue3t {
+    use crate::types::{Tissue, TissueProperties};
+
+    pub fn get_tissue(tissue: Tissue) -> TissueProperties {
+        match tissue {
+            Tissue::WhiteMatter => TissueProperties {
+                name: "wm".into(),
+                pd: 0.69,
+                t1: 1.084,
+                t2: 0.069,
+                t2s: 0.053,
+                adc: 0.7e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::GreyMatter => TissueProperties {
+                name: "gm".into(),
+                pd: 0.82,
+                t1: 1.82,
+                t2: 0.099,
+                t2s: 0.066,
+                adc: 0.8e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::Caudate => TissueProperties {
+                name: "caudate".into(),
+                pd: 0.82,
+                t1: 1.43,
+                t2: 0.085,
+                t2s: 0.053,
+                adc: 0.75e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::Thalamus => TissueProperties {
+                name: "thalamus".into(),
+                pd: 0.82,
+                t1: 1.2,
+                t2: 0.075,
+                t2s: 0.055,
+                adc: 0.75e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::CerebroSpinalFluid => TissueProperties {
+                name: "csf".into(),
+                pd: 1.0,
+                t1: 4.16,
+                t2: 1.8,
+                t2s: 0.15,
+                adc: 3.0e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::Blood => TissueProperties {
+                name: "blood".into(),
+                pd: 1.0,
+                t1: 1.932,
+                t2: 0.275,
+                t2s: 0.035,
+                adc: 1.6e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+        }
+    }
+}
+
+/// 7T
+pub mod tissue7t {
+    use crate::types::{Tissue, TissueProperties};
+
+    pub fn get_tissue(tissue: Tissue) -> TissueProperties {
+        match tissue {
+            Tissue::WhiteMatter => TissueProperties {
+                name: "wm".into(),
+                pd: 0.69,
+                t1: 1.22,
+                t2: 0.046,
+                t2s: 0.026,
+                adc: 0.7e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::GreyMatter => TissueProperties {
+                name: "gm".into(),
+                pd: 0.82,
+                t1: 2.13,
+                t2: 0.055,
+                t2s: 0.033,
+                adc: 0.8e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::Caudate => TissueProperties {
+                name: "caudate".into(),
+                pd: 0.82,
+                t1: 1.68,
+                t2: 0.048,
+                t2s: 0.025,
+                adc: 0.75e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::Thalamus => TissueProperties {
+                name: "thalamus".into(),
+                pd: 0.82,
+                t1: 1.6,
+                t2: 0.045,
+                t2s: 0.027,
+                adc: 0.75e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::CerebroSpinalFluid => TissueProperties {
+                name: "csf".into(),
+                pd: 1.0,
+                t1: 4.43,
+                t2: 1.0,
+                t2s: 0.1,
+                adc: 3.0e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+            Tissue::Blood => TissueProperties {
+                name: "blood".into(),
+                pd: 1.0,
+                t1: 2.587,
+                t2: 0.068,
+                t2s: 0.015,
+                adc: 1.6e-9,
+                compartments: vec![],
+                exchange: vec![],
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::dephasing::Dephasing;
+    use crate::sequences::fid::FidParams;
+    use crate::sequences::gre::GreParams;
+    use crate::sequences::{Sequence, Spoiling};
+
+    #[test]
+    fn test_t1_lengthens_with_field() {
+        for tissue in [Tissue::WhiteMatter, Tissue::GreyMatter, Tissue::Blood] {
+            let t1: Vec<f64> = FieldStrength::ALL
+                .iter()
+                .map(|&f| get_tissue(tissue, f).t1)
+                .collect();
+            assert!(t1.windows(2).all(|w| w[0] < w[1]));
+        }
+    }
+
+    #[test]
+    fn test_tissues_have_distinct_relaxation() {
+        for field in FieldStrength::ALL {
+            for (ix, &a) in Tissue::ALL.iter().enumerate() {
+                for &b in Tissue::ALL[ix + 1..].iter() {
+                    let (a, b) = (get_tissue(a, field), get_tissue(b, field));
+                    assert!(
+                        (a.t1, a.t2) != (b.t1, b.t2),
+                        "{} and {} share T1 and T2 at {:?}",
+                        a.name,
+                        b.name,
+                        field
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_tissue_reads_with_t2_star() {
+        let fid = FidParams {
+            nreads: 1,
+            t1: 1.0,
+            t2: 0.1,
+            echo_time: 0.01,
+            dephasing: Dephasing::None,
+            debug_print: false,
+        };
+        let gre = GreParams {
+            t1: 1.0,
+            t2: 0.1,
+            flip_angle: 20.0_f64.to_radians(),
+            tr: 0.02,
+            echo_time: 0.01,
+            nreads: 10,
+            spoiling: Spoiling::Ideal,
+            dephasing: Dephasing::None,
+            debug_print: false,
+        };
+        for field in FieldStrength::ALL {
+            for tissue in Tissue::ALL {
+                let props = get_tissue(tissue, field);
+                assert!(props.t2s <= props.t2, "{} at {:?}", props.name, field);
+
+                let signal = fid.simulate(&props).unwrap();
+                let expected = props.pd * (-0.01 / props.t2s).exp();
+                assert!((signal[0].norm() - expected).abs() < 1e-9);
+                assert!(gre.simulate(&props).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_nearest_field() {
+        assert_eq!(FieldStrength::nearest(0.5), FieldStrength::T0_55);
+        assert_eq!(FieldStrength::nearest(2.9), FieldStrength::T3);
+        assert_eq!(FieldStrength::nearest(9.4), FieldStrength::T7);
+    }
+}
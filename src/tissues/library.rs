@@ -0,0 +1,539 @@
+//! User tissue libraries, loaded from TOML or JSON and merged with the built-in tables.
+//!
+//! Both formats hold a list of tissues under `tissue`:
+//!
+//! ```toml
+//! [[tissue]]
+//! name = "muscle"
+//! pd = 0.7
+//! t1 = 1.42
+//! t2 = 0.032
+//! t2s = 0.028
+//! adc = 1.5e-9          # optional, m^2/s
+//! ```
+//!
+//! `t2s` may not exceed `t2`, for the tissue and for each compartment.
+//!
+//! Multi-compartment tissues list their compartments and any exchange between them:
+//!
+//! ```toml
+//! [[tissue.compartment]]
+//! name = "myelin"
+//! fraction = 0.15
+//! t1 = 0.4
+//! t2 = 0.015
+//! t2s = 0.01
+//!
+//! [[tissue.exchange]]
+//! from = "myelin"
+//! to = "axonal"
+//! rate = 4.0            # 1/s
+//! ```
+
+use std::fmt;
+use std::path::Path;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use super::{get_tissue, FieldStrength};
+use crate::types::{Compartment, Exchange, Tissue, TissueProperties};
+
+/// Error loading a library. `line` is one-based and points at the offending entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LibraryError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for LibraryError {}
+
+impl From<toml::de::Error> for LibraryError {
+    fn from(e: toml::de::Error) -> Self {
+        let mut message = e.to_string();
+        // toml appends the position to the message, it is reported in `line` instead
+        if let Some(ix) = message.find(" at line ") {
+            message.truncate(ix);
+        }
+        LibraryError {
+            line: e.line_col().map(|(line, _)| line + 1),
+            message,
+        }
+    }
+}
+
+impl From<serde_json::Error> for LibraryError {
+    fn from(e: serde_json::Error) -> Self {
+        let mut message = e.to_string();
+        if let Some(ix) = message.find(" at line ") {
+            message.truncate(ix);
+        }
+        LibraryError {
+            line: Some(e.line()).filter(|&line| line > 0),
+            message,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LibraryFile {
+    tissue: Vec<TissueEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CompartmentEntry {
+    #[serde(deserialize_with = "name")]
+    name: String,
+    #[serde(deserialize_with = "positive")]
+    fraction: f64,
+    #[serde(deserialize_with = "positive")]
+    t1: f64,
+    #[serde(deserialize_with = "positive")]
+    t2: f64,
+    #[serde(deserialize_with = "positive")]
+    t2s: f64,
+    #[serde(default, deserialize_with = "non_negative")]
+    adc: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExchangeEntry {
+    from: String,
+    to: String,
+    #[serde(deserialize_with = "non_negative")]
+    rate: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TissueEntry {
+    #[serde(deserialize_with = "name")]
+    name: String,
+    #[serde(deserialize_with = "non_negative")]
+    pd: f64,
+    #[serde(deserialize_with = "positive")]
+    t1: f64,
+    #[serde(deserialize_with = "positive")]
+    t2: f64,
+    #[serde(deserialize_with = "positive")]
+    t2s: f64,
+    #[serde(default, deserialize_with = "non_negative")]
+    adc: f64,
+    #[serde(default)]
+    compartment: Vec<CompartmentEntry>,
+    #[serde(default)]
+    exchange: Vec<ExchangeEntry>,
+}
+
+// Values are checked inside the visitors, so the parsers report the value's own position.
+
+fn name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    struct NameVisitor;
+
+    impl<'de> Visitor<'de> for NameVisitor {
+        type Value = String;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a tissue name")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<String, E> {
+            if v.trim().is_empty() {
+                return Err(E::custom("tissue name is empty"));
+            }
+            Ok(v.to_string())
+        }
+    }
+
+    deserializer.deserialize_str(NameVisitor)
+}
+
+struct BoundedVisitor {
+    allow_zero: bool,
+}
+
+impl<'de> Visitor<'de> for BoundedVisitor {
+    type Value = f64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.allow_zero {
+            true => write!(f, "a non-negative number"),
+            false => write!(f, "a positive number"),
+        }
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<f64, E> {
+        let valid = v.is_finite() && (v > 0.0 || (self.allow_zero && v == 0.0));
+        if !valid {
+            return Err(E::invalid_value(de::Unexpected::Float(v), &self));
+        }
+        Ok(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<f64, E> {
+        self.visit_f64(v as f64)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<f64, E> {
+        self.visit_f64(v as f64)
+    }
+}
+
+fn positive<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    deserializer.deserialize_f64(BoundedVisitor { allow_zero: false })
+}
+
+fn non_negative<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    deserializer.deserialize_f64(BoundedVisitor { allow_zero: true })
+}
+
+fn unknown_field(message: &str) -> Option<&str> {
+    message.strip_prefix("unknown field `")?.split('`').next()
+}
+
+/// Whether `line` sets `key`, as `key = ...` in TOML or `"key": ...` in JSON.
+fn sets_key(line: &str, key: &str) -> bool {
+    let json = line.match_indices(&format!("\"{}\"", key)).any(|(ix, quoted)| {
+        line[ix + quoted.len()..].trim_start().starts_with(':')
+    });
+    json || line.split('=').next().map(str::trim) == Some(key)
+}
+
+/// Error spanning several fields of an entry, checked once the file is parsed. `at`
+/// leads from the tissue name to the offending line as (key, value) pairs, each found at
+/// or below the previous one.
+struct EntryError {
+    at: Vec<(&'static str, Option<String>)>,
+    message: String,
+}
+
+impl EntryError {
+    fn new(tissue: &str, message: String) -> Self {
+        Self {
+            at: vec![("name", Some(tissue.to_string()))],
+            message,
+        }
+    }
+
+    fn at(mut self, key: &'static str, value: Option<&str>) -> Self {
+        self.at.push((key, value.map(str::to_string)));
+        self
+    }
+
+    fn locate(self, text: &str) -> LibraryError {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut line = Some(0);
+        for (key, value) in self.at.iter() {
+            let quoted = value.as_ref().map(|v| format!("\"{}\"", v));
+            line = line.and_then(|start| {
+                (start..lines.len()).find(|&ix| {
+                    sets_key(lines[ix], key)
+                        && quoted.as_ref().is_none_or(|q| lines[ix].contains(q.as_str()))
+                })
+            });
+        }
+        LibraryError {
+            line: line.map(|ix| ix + 1),
+            message: self.message,
+        }
+    }
+}
+
+impl TissueEntry {
+    fn into_tissue(self) -> Result<TissueProperties, EntryError> {
+        let error = |message: String| EntryError::new(&self.name, message);
+
+        if self.t2s > self.t2 {
+            let message = format!("{}: T2* {} exceeds T2 {}", self.name, self.t2s, self.t2);
+            return Err(error(message).at("t2s", None));
+        }
+        for c in self.compartment.iter() {
+            if c.t2s > c.t2 {
+                let message = format!(
+                    "{}: T2* {} of compartment `{}` exceeds T2 {}",
+                    self.name, c.t2s, c.name, c.t2
+                );
+                return Err(error(message)
+                    .at("name", Some(&c.name))
+                    .at("t2s", None));
+            }
+        }
+
+        let index = |key: &'static str, name: &str| {
+            self.compartment
+                .iter()
+                .position(|c| c.name == name)
+                .ok_or_else(|| {
+                    error(format!("{}: unknown compartment `{}`", self.name, name))
+                        .at(key, Some(name))
+                })
+        };
+        let exchange = self
+            .exchange
+            .iter()
+            .map(|ex| {
+                Ok(Exchange {
+                    from: index("from", &ex.from)?,
+                    to: index("to", &ex.to)?,
+                    rate: ex.rate,
+                })
+            })
+            .collect::<Result<Vec<Exchange>, EntryError>>()?;
+
+        let compartments = self
+            .compartment
+            .iter()
+            .map(|c| Compartment {
+                name: c.name.clone(),
+                fraction: c.fraction,
+                t1: c.t1,
+                t2: c.t2,
+                t2s: c.t2s,
+                adc: c.adc,
+            })
+            .collect();
+        let tissue = TissueProperties {
+            name: self.name.clone(),
+            pd: self.pd,
+            t1: self.t1,
+            t2: self.t2,
+            t2s: self.t2s,
+            adc: self.adc,
+            compartments,
+            exchange,
+        };
+        tissue.validate().map_err(error)?;
+        Ok(tissue)
+    }
+}
+
+/// Tissues looked up by name. Later entries with the same name replace earlier ones.
+#[derive(Clone, Debug, Default)]
+pub struct TissueLibrary {
+    tissues: Vec<TissueProperties>,
+}
+
+impl TissueLibrary {
+    /// Every built-in tissue at `field`.
+    pub fn builtin(field: FieldStrength) -> Self {
+        let mut library = Self::default();
+        for tissue in Tissue::ALL {
+            library.insert(get_tissue(tissue, field));
+        }
+        library
+    }
+
+    pub fn from_toml_str(text: &str) -> Result<Self, LibraryError> {
+        let file: LibraryFile = toml::from_str(text).map_err(|e| {
+            let mut error = LibraryError::from(e);
+            // toml reports unknown keys at the end of their table, move back to the key
+            if let (Some(line), Some(key)) = (error.line, unknown_field(&error.message)) {
+                error.line = text
+                    .lines()
+                    .take(line)
+                    .enumerate()
+                    .filter(|(_, l)| sets_key(l, key))
+                    .map(|(ix, _)| ix + 1)
+                    .last()
+                    .or(error.line);
+            }
+            error
+        })?;
+        Self::from_entries(file.tissue, text)
+    }
+
+    pub fn from_json_str(text: &str) -> Result<Self, LibraryError> {
+        let file: LibraryFile = serde_json::from_str(text)?;
+        Self::from_entries(file.tissue, text)
+    }
+
+    /// Load a `.toml` or `.json` library file.
+    pub fn load(path: &Path) -> Result<Self, LibraryError> {
+        let error = |message: String| LibraryError {
+            line: None,
+            message: format!("{}: {}", path.display(), message),
+        };
+        let text = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&text),
+            Some("json") => Self::from_json_str(&text),
+            _ => Err(error("expected a .toml or .json tissue library".into())),
+        }
+    }
+
+    /// Check the parsed entries, locating errors in the source `text`.
+    fn from_entries(entries: Vec<TissueEntry>, text: &str) -> Result<Self, LibraryError> {
+        let mut library = Self::default();
+        for entry in entries {
+            library.insert(entry.into_tissue().map_err(|e| e.locate(text))?);
+        }
+        Ok(library)
+    }
+
+    /// Add `tissue`, replacing any tissue of the same name.
+    pub fn insert(&mut self, tissue: TissueProperties) {
+        match self.tissues.iter_mut().find(|t| t.name == tissue.name) {
+            Some(existing) => *existing = tissue,
+            None => self.tissues.push(tissue),
+        }
+    }
+
+    /// Add every tissue of `other`, which takes precedence on name clashes.
+    pub fn merge(&mut self, other: TissueLibrary) {
+        for tissue in other.tissues {
+            self.insert(tissue);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TissueProperties> {
+        self.tissues.iter().find(|t| t.name == name)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.tissues.iter().map(|t| t.name.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MUSCLE: &str = r#"
+[[tissue]]
+name = "muscle"
+pd = 0.7
+t1 = 1.42
+t2 = 0.032
+t2s = 0.028
+adc = 1.5e-9
+
+[[tissue]]
+name = "wm"
+pd = 0.7
+t1 = 1.1
+t2 = 0.07
+t2s = 0.05
+"#;
+
+    #[test]
+    fn test_merge_with_builtin() {
+        let mut library = TissueLibrary::builtin(FieldStrength::T3);
+        library.merge(TissueLibrary::from_toml_str(MUSCLE).unwrap());
+
+        assert_eq!(library.names().len(), 7);
+        assert_eq!(library.get("muscle").unwrap().adc, 1.5e-9);
+        assert_eq!(library.get("wm").unwrap().t1, 1.1);
+        assert_eq!(library.get("gm").unwrap().t1, 1.82);
+    }
+
+    #[test]
+    fn test_json_matches_toml() {
+        let json = r#"{"tissue": [
+            {"name": "muscle", "pd": 0.7, "t1": 1.42, "t2": 0.032, "t2s": 0.028}
+        ]}"#;
+        let library = TissueLibrary::from_json_str(json).unwrap();
+        assert_eq!(library.get("muscle").unwrap().t2, 0.032);
+
+        // off-resonance belongs to the sequence, not the tissue
+        let json = r#"{"tissue": [
+            {"name": "muscle", "pd": 0.7, "t1": 1.42, "t2": 0.032, "t2s": 0.028,
+             "off_resonance": 10.0}
+        ]}"#;
+        let err = TissueLibrary::from_json_str(json).unwrap_err();
+        assert!(err.message.contains("off_resonance"));
+    }
+
+    const MYELINATED: &str = r#"
+[[tissue]]
+name = "wm"
+pd = 0.7
+t1 = 1.1
+t2 = 0.07
+t2s = 0.05
+
+[[tissue.compartment]]
+name = "myelin"
+fraction = 0.15
+t1 = 0.4
+t2 = 0.015
+t2s = 0.01
+
+[[tissue.compartment]]
+name = "axonal"
+fraction = 0.85
+t1 = 1.1
+t2 = 0.08
+t2s = 0.06
+
+[[tissue.exchange]]
+from = "myelin"
+to = "axonal"
+rate = 4.0
+"#;
+
+    #[test]
+    fn test_compartments() {
+        let library = TissueLibrary::from_toml_str(MYELINATED).unwrap();
+        let wm = library.get("wm").unwrap();
+        assert_eq!(wm.compartments.len(), 2);
+        assert_eq!((wm.exchange[0].from, wm.exchange[0].to), (0, 1));
+
+        let unknown = MYELINATED.replace("to = \"axonal\"", "to = \"axon\"");
+        let err = TissueLibrary::from_toml_str(&unknown).unwrap_err();
+        assert!(err.message.contains("unknown compartment `axon`"));
+        assert_eq!(err.line, Some(25));
+
+        // errors of the entry as a whole point at its name
+        let fractions = MYELINATED.replace("fraction = 0.85", "fraction = 0.8");
+        let err = TissueLibrary::from_toml_str(&fractions).unwrap_err();
+        assert!(err.message.contains("fractions sum to"));
+        assert_eq!(err.line, Some(3));
+    }
+
+    #[test]
+    fn test_rejects_t2_star_above_t2() {
+        let tissue = MUSCLE.replace("t2s = 0.028", "t2s = 0.04");
+        let err = TissueLibrary::from_toml_str(&tissue).unwrap_err();
+        assert!(err.message.contains("exceeds T2"));
+        assert_eq!(err.line, Some(7));
+
+        let compartment = MYELINATED.replace("t2s = 0.06", "t2s = 0.09");
+        let err = TissueLibrary::from_toml_str(&compartment).unwrap_err();
+        assert!(err.message.contains("compartment `axonal`"));
+        assert_eq!(err.line, Some(21));
+
+        let json = r#"{"tissue": [
+  {"name": "x", "pd": 1.0, "t1": 1.0,
+   "t2": 0.1,
+   "t2s": 0.2}
+]}"#;
+        assert_eq!(TissueLibrary::from_json_str(json).unwrap_err().line, Some(4));
+    }
+
+    #[test]
+    fn test_errors_point_at_line() {
+        let bad_t2 = MUSCLE.replace("t2 = 0.07", "t2 = -0.07");
+        let err = TissueLibrary::from_toml_str(&bad_t2).unwrap_err();
+        assert_eq!(err.line, Some(14));
+        assert!(err.message.contains("positive number"));
+
+        let typo = MUSCLE.replace("adc =", "adcc =");
+        assert_eq!(TissueLibrary::from_toml_str(&typo).unwrap_err().line, Some(8));
+
+        let json = "{\"tissue\": [\n  {\"name\": \"x\", \"pd\": 1.0,\n   \"t1\": 0.0, \"t2\": 0.1, \"t2s\": 0.1}\n]}";
+        assert_eq!(TissueLibrary::from_json_str(json).unwrap_err().line, Some(3));
+    }
+}
@@ -1,18 +1,24 @@
 use ndarray::{Array, Ix2};
 use num_complex::Complex64;
 
+#[allow(clippy::upper_case_acronyms)]
 pub(crate) trait EPG {
     // This trait describes the interface for a mutable echo phase graph.
     fn new(n_states: usize) -> Self;
     fn read(&self) -> Complex64;
+    fn read_mz(&self) -> Complex64;
     fn excite(&mut self);
     fn rotate(&mut self, rmat: &Array<Complex64, Ix2>);
     fn spoil(&mut self, ntwists: i32);
+    fn crush(&mut self);
     fn grelax(&mut self, et1d: Complex64, et2d: Complex64, ntwists: i32);
     fn delay(&mut self, et1d: Complex64, et2d: Complex64);
+    fn precess(&mut self, phase: f64);
+    fn diffuse(&mut self, adc: f64, dt: f64, dk: f64, ntwists: i32);
 }
 
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Tissue {
     WhiteMatter,
     GreyMatter,
@@ -22,10 +28,99 @@ pub enum Tissue {
     Blood
 }
 
+impl Tissue {
+    /// Every tissue with a built-in relaxation table.
+    pub const ALL: [Tissue; 6] = [
+        Tissue::WhiteMatter,
+        Tissue::GreyMatter,
+        Tissue::Caudate,
+        Tissue::CerebroSpinalFluid,
+        Tissue::Thalamus,
+        Tissue::Blood,
+    ];
+}
+
+#[derive(Clone, Debug)]
 pub struct TissueProperties {
-    pub name: &'static str,
-    pub pd: f32,
-    pub t1: f32,
-    pub t2: f32,
-    pub t2s: f32,
+    pub name: String,
+    pub pd: f64,
+    pub t1: f64,
+    pub t2: f64,
+    pub t2s: f64,
+    /// Apparent diffusion coefficient in m^2/s.
+    pub adc: f64,
+    /// Water compartments, by volume fraction. When empty the tissue is a single
+    /// compartment with the relaxation above.
+    pub compartments: Vec<Compartment>,
+    /// Exchange between compartments.
+    pub exchange: Vec<Exchange>,
+}
+
+impl TissueProperties {
+    /// Check compartment fractions sum to one, T2* does not exceed T2 and exchange
+    /// refers to compartments.
+    pub fn validate(&self) -> Result<(), String> {
+        let pools = self.pools();
+        if self.t2s > self.t2 {
+            return Err(format!("{}: T2* {} exceeds T2 {}", self.name, self.t2s, self.t2));
+        }
+        if let Some(c) = pools.iter().find(|c| c.t2s > c.t2) {
+            return Err(format!(
+                "{}: T2* {} of compartment `{}` exceeds T2 {}",
+                self.name, c.t2s, c.name, c.t2
+            ));
+        }
+        let total: f64 = pools.iter().map(|c| c.fraction).sum();
+        if (total - 1.0).abs() > 1e-6 {
+            return Err(format!("{}: compartment fractions sum to {}", self.name, total));
+        }
+        if pools.iter().any(|c| c.fraction <= 0.0) {
+            return Err(format!("{}: compartment fractions must be positive", self.name));
+        }
+        for ex in self.exchange.iter() {
+            if ex.from == ex.to || ex.from >= pools.len() || ex.to >= pools.len() {
+                return Err(format!(
+                    "{}: exchange between compartments {} and {} is invalid",
+                    self.name, ex.from, ex.to
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Compartments of the tissue, or the tissue itself as a single compartment.
+    pub fn pools(&self) -> Vec<Compartment> {
+        if !self.compartments.is_empty() {
+            return self.compartments.clone();
+        }
+        vec![Compartment {
+            name: self.name.clone(),
+            fraction: 1.0,
+            t1: self.t1,
+            t2: self.t2,
+            t2s: self.t2s,
+            adc: self.adc,
+        }]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Compartment {
+    pub name: String,
+    /// Fraction of the tissue water, fractions of a tissue sum to one.
+    pub fraction: f64,
+    pub t1: f64,
+    pub t2: f64,
+    pub t2s: f64,
+    pub adc: f64,
+}
+
+/// First-order exchange between two compartments, given by index. The reverse rate
+/// follows from detailed balance, `fraction[from] * rate = fraction[to] * reverse`.
+#[derive(Clone, Copy, Debug)]
+pub struct Exchange {
+    pub from: usize,
+    pub to: usize,
+    /// Forward rate in 1/s.
+    pub rate: f64,
 }
\ No newline at end of file
@@ -0,0 +1,443 @@
+//! Monte Carlo propagation of tissue parameter uncertainty through sequence simulations.
+
+use num_complex::Complex64;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+
+use crate::sequences::Sequence;
+use crate::types::TissueProperties;
+
+/// Tissue property that can be drawn from a distribution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Property {
+    Pd,
+    T1,
+    T2,
+    T2s,
+    Adc,
+}
+
+/// Distribution of a property value. Draws outside the physical range of the
+/// property, such as a negative T1, are rejected and redrawn.
+#[derive(Clone, Copy, Debug)]
+pub enum Spread {
+    Normal {
+        mean: f64,
+        sd: f64,
+    },
+    /// `median` is the geometric mean and `sigma` the standard deviation of the log.
+    LogNormal {
+        median: f64,
+        sigma: f64,
+    },
+    Uniform {
+        min: f64,
+        max: f64,
+    },
+}
+
+impl Spread {
+    /// Normal distribution about `value` with coefficient of variation `cv`.
+    pub fn relative(value: f64, cv: f64) -> Self {
+        Spread::Normal {
+            mean: value,
+            sd: cv * value.abs(),
+        }
+    }
+
+    /// Check the spread describes a distribution.
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Spread::Normal { mean, sd } => {
+                if !mean.is_finite() || !sd.is_finite() || sd < 0.0 {
+                    return Err("normal spread needs a finite mean and non-negative sd".into());
+                }
+            }
+            Spread::LogNormal { median, sigma } => {
+                if !median.is_finite() || median <= 0.0 {
+                    return Err("log-normal median must be positive".into());
+                }
+                if !sigma.is_finite() || sigma < 0.0 {
+                    return Err("log-normal sigma must be finite and non-negative".into());
+                }
+            }
+            Spread::Uniform { min, max } => {
+                if !min.is_finite() || !max.is_finite() || min > max {
+                    return Err("uniform bounds must be finite and ordered".into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Draw of a spread that passed `validate`.
+    fn sample(&self, rng: &mut StdRng) -> f64 {
+        match *self {
+            Spread::Normal { mean, sd } => mean + sd * rng.sample::<f64, _>(StandardNormal),
+            Spread::LogNormal { median, sigma } => {
+                median * (sigma * rng.sample::<f64, _>(StandardNormal)).exp()
+            }
+            Spread::Uniform { min, max } => min + (max - min) * rng.gen::<f64>(),
+        }
+    }
+}
+
+/// Tissue whose properties are point estimates unless given a spread.
+#[derive(Clone, Debug)]
+pub struct UncertainTissue {
+    pub tissue: TissueProperties,
+    pub spreads: Vec<(Property, Spread)>,
+}
+
+impl UncertainTissue {
+    pub fn new(tissue: TissueProperties) -> Self {
+        Self {
+            tissue,
+            spreads: vec![],
+        }
+    }
+
+    /// Draw `property` from `spread`, replacing any spread it already had.
+    pub fn with(mut self, property: Property, spread: Spread) -> Self {
+        self.spreads.retain(|(p, _)| *p != property);
+        self.spreads.push((property, spread));
+        self
+    }
+
+    /// Check every spread describes a distribution.
+    pub fn validate(&self) -> Result<(), String> {
+        for (property, spread) in self.spreads.iter() {
+            spread
+                .validate()
+                .map_err(|e| format!("{}: {:?}: {}", self.tissue.name, property, e))?;
+        }
+        Ok(())
+    }
+
+    /// One realisation of the tissue. Compartment relaxation and diffusion scale
+    /// with the drawn tissue value, keeping their ratios to it. Fails if a spread is
+    /// invalid or keeps drawing values outside the physical range of its property.
+    pub fn sample(&self, rng: &mut StdRng) -> Result<TissueProperties, String> {
+        self.validate()?;
+        let mut tissue = self.tissue.clone();
+        for (property, spread) in self.spreads.iter() {
+            let valid = |x: f64| match property {
+                Property::T1 | Property::T2 | Property::T2s => x > 0.0,
+                Property::Pd | Property::Adc => x >= 0.0,
+            };
+            let value = (0..MAX_REDRAWS)
+                .map(|_| spread.sample(rng))
+                .find(|&x| valid(x))
+                .ok_or_else(|| format!("{:?} spread has no valid values", property))?;
+
+            let scale = |nominal: f64, x: &mut f64| {
+                *x = if nominal > 0.0 {
+                    *x * value / nominal
+                } else {
+                    value
+                }
+            };
+            match property {
+                Property::Pd => tissue.pd = value,
+                Property::T1 => {
+                    for c in tissue.compartments.iter_mut() {
+                        scale(self.tissue.t1, &mut c.t1);
+                    }
+                    tissue.t1 = value;
+                }
+                Property::T2 => {
+                    for c in tissue.compartments.iter_mut() {
+                        scale(self.tissue.t2, &mut c.t2);
+                    }
+                    tissue.t2 = value;
+                }
+                Property::T2s => {
+                    for c in tissue.compartments.iter_mut() {
+                        scale(self.tissue.t2s, &mut c.t2s);
+                    }
+                    tissue.t2s = value;
+                }
+                Property::Adc => {
+                    for c in tissue.compartments.iter_mut() {
+                        scale(self.tissue.adc, &mut c.adc);
+                    }
+                    tissue.adc = value;
+                }
+            }
+        }
+        Ok(tissue)
+    }
+}
+
+/// Statistics of a sampled quantity.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    pub mean: f64,
+    pub sd: f64,
+    sorted: Vec<f64>,
+}
+
+impl Summary {
+    fn new(mut samples: Vec<f64>) -> Self {
+        assert!(!samples.is_empty(), "at least one sample");
+        samples.sort_by(f64::total_cmp);
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let var = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+        Self {
+            mean,
+            sd: var.sqrt(),
+            sorted: samples,
+        }
+    }
+
+    /// Value below which `p` percent of the samples fall, linearly interpolated.
+    pub fn percentile(&self, p: f64) -> f64 {
+        assert!((0.0..=100.0).contains(&p), "percentile must be in [0, 100]");
+        let pos = p / 100.0 * (self.sorted.len() - 1) as f64;
+        let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+        self.sorted[lo] + (pos - lo as f64) * (self.sorted[hi] - self.sorted[lo])
+    }
+
+    pub fn median(&self) -> f64 {
+        self.percentile(50.0)
+    }
+
+    pub fn samples(&self) -> &[f64] {
+        &self.sorted
+    }
+}
+
+/// Draws of a property, or of a tissue, tried before giving up on it.
+const MAX_REDRAWS: usize = 1000;
+
+/// Seeded Monte Carlo sampler running `ndraws` tissue realisations per simulation.
+pub struct Sampler {
+    pub ndraws: usize,
+    rng: StdRng,
+}
+
+impl Sampler {
+    pub fn new(ndraws: usize, seed: u64) -> Self {
+        assert!(ndraws > 0, "at least one draw");
+        Self {
+            ndraws,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Signal magnitude of every readout of `sequence` over draws of `tissue`. Fails if
+    /// the sequence or a spread is invalid, or a draw keeps failing tissue validation.
+    pub fn signal(
+        &mut self,
+        sequence: &dyn Sequence,
+        tissue: &UncertainTissue,
+    ) -> Result<Vec<Summary>, String> {
+        sequence.validate()?;
+        tissue.validate()?;
+        let draws = (0..self.ndraws)
+            .map(|_| {
+                let signal = self.simulate(sequence, tissue)?;
+                Ok(signal.iter().map(|s| s.norm()).collect())
+            })
+            .collect::<Result<Vec<Vec<f64>>, String>>()?;
+        Ok(summarise(draws))
+    }
+
+    /// Contrast `|S_a - S_b|` of every readout of `sequence`, with `a` and `b` drawn
+    /// independently.
+    pub fn contrast(
+        &mut self,
+        sequence: &dyn Sequence,
+        a: &UncertainTissue,
+        b: &UncertainTissue,
+    ) -> Result<Vec<Summary>, String> {
+        sequence.validate()?;
+        a.validate()?;
+        b.validate()?;
+        let draws = (0..self.ndraws)
+            .map(|_| {
+                let sa = self.simulate(sequence, a)?;
+                let sb = self.simulate(sequence, b)?;
+                Ok(sa.iter().zip(sb.iter()).map(|(a, b)| (a - b).norm()).collect())
+            })
+            .collect::<Result<Vec<Vec<f64>>, String>>()?;
+        Ok(summarise(draws))
+    }
+
+    /// Signal of one draw of `tissue`. Draws the sequence rejects, such as T2* above
+    /// T2, are redrawn, truncating the spreads to valid tissues.
+    fn simulate(
+        &mut self,
+        sequence: &dyn Sequence,
+        tissue: &UncertainTissue,
+    ) -> Result<Vec<Complex64>, String> {
+        let mut error = String::new();
+        for _ in 0..MAX_REDRAWS {
+            match sequence.simulate(&tissue.sample(&mut self.rng)?) {
+                Ok(signal) => return Ok(signal),
+                Err(e) => error = e,
+            }
+        }
+        Err(format!("no valid draw of {}: {}", tissue.tissue.name, error))
+    }
+}
+
+/// Per-readout statistics of draws indexed [draw][readout].
+fn summarise(draws: Vec<Vec<f64>>) -> Vec<Summary> {
+    let nreads = draws.iter().map(|d| d.len()).min().unwrap_or(0);
+    (0..nreads)
+        .map(|echo| Summary::new(draws.iter().map(|d| d[echo]).collect()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequences::se::SeParams;
+    use crate::tissues::{get_tissue, FieldStrength};
+    use crate::types::Tissue;
+    use std::f64::consts::PI;
+
+    fn spin_echo() -> SeParams {
+        SeParams {
+            t1: 1.0,
+            t2: 0.1,
+            refocus_angle: PI,
+            refocus_phase: PI / 2.0,
+            echo_time: 0.1,
+            debug_print: false,
+        }
+    }
+
+    #[test]
+    fn test_point_estimates_have_no_spread() {
+        let wm = get_tissue(Tissue::WhiteMatter, FieldStrength::T3);
+        let expected = spin_echo().simulate(&wm).unwrap()[0].norm();
+        let res = Sampler::new(10, 1).signal(&spin_echo(), &UncertainTissue::new(wm)).unwrap();
+        assert!((res[0].mean - expected).abs() < 1e-12);
+        assert!(res[0].sd < 1e-12);
+        assert_eq!(res[0].percentile(5.0), res[0].percentile(95.0));
+    }
+
+    #[test]
+    fn test_uniform_t2_spin_echo() {
+        // mean of exp(-TE / T2) with uniform R2 = 1 / T2 over [10, 20] 1/s
+        // T2* below every draw, so none is rejected
+        let tissue = TissueProperties {
+            pd: 1.0,
+            t2s: 0.04,
+            ..get_tissue(Tissue::WhiteMatter, FieldStrength::T3)
+        };
+        let uncertain = UncertainTissue::new(tissue).with(
+            Property::T2,
+            Spread::Uniform {
+                min: 0.05,
+                max: 0.1,
+            },
+        );
+        let res = Sampler::new(4000, 2).signal(&spin_echo(), &uncertain).unwrap();
+
+        let (lo, hi) = ((-0.1_f64 / 0.05).exp(), (-0.1_f64 / 0.1).exp());
+        assert!(res[0].percentile(0.0) >= lo - 1e-12);
+        assert!(res[0].percentile(100.0) <= hi + 1e-12);
+        assert!(res[0].percentile(25.0) < res[0].median());
+        assert!(res[0].median() < res[0].percentile(75.0));
+
+        // integral of exp(-TE / T2) for T2 uniform on [0.05, 0.1], by midpoint rule
+        let expected = (0..1000)
+            .map(|ix| (-0.1 / (0.05 + (ix as f64 + 0.5) * 0.05e-3)).exp())
+            .sum::<f64>()
+            / 1000.0;
+        assert!((res[0].mean - expected).abs() < 3.0 * res[0].sd / (4000.0_f64).sqrt());
+    }
+
+    #[test]
+    fn test_contrast_is_reproducible() {
+        let wm = UncertainTissue::new(get_tissue(Tissue::WhiteMatter, FieldStrength::T3)).with(
+            Property::T2,
+            Spread::LogNormal {
+                median: 0.07,
+                sigma: 0.1,
+            },
+        );
+        let gm = UncertainTissue::new(get_tissue(Tissue::GreyMatter, FieldStrength::T3))
+            .with(Property::Pd, Spread::relative(0.8, 0.05));
+
+        let a = Sampler::new(200, 3).contrast(&spin_echo(), &wm, &gm).unwrap();
+        let b = Sampler::new(200, 3).contrast(&spin_echo(), &wm, &gm).unwrap();
+        assert_eq!(a[0].samples(), b[0].samples());
+        assert!(a[0].sd > 0.0);
+    }
+
+    #[test]
+    fn test_invalid_draws_are_redrawn() {
+        // T2 draws below the fixed T2* fail validation
+        let wm = get_tissue(Tissue::WhiteMatter, FieldStrength::T3);
+        let t2s = wm.t2s;
+        let uncertain = UncertainTissue::new(wm).with(
+            Property::T2,
+            Spread::Uniform {
+                min: 0.03,
+                max: 0.1,
+            },
+        );
+        let res = Sampler::new(500, 4).signal(&spin_echo(), &uncertain).unwrap();
+        let shortest = 0.69 * (-0.1 / t2s).exp();
+        assert!(res[0].percentile(0.0) >= shortest - 1e-12);
+
+        let never = uncertain.clone().with(
+            Property::T2,
+            Spread::Uniform {
+                min: 0.01,
+                max: 0.02,
+            },
+        );
+        let err = Sampler::new(10, 4).signal(&spin_echo(), &never).unwrap_err();
+        assert!(err.contains("no valid draw"));
+
+        let invalid = SeParams {
+            echo_time: -0.1,
+            ..spin_echo()
+        };
+        assert!(Sampler::new(10, 4).signal(&invalid, &uncertain).is_err());
+    }
+
+    #[test]
+    fn test_invalid_spreads_are_rejected() {
+        let wm = get_tissue(Tissue::WhiteMatter, FieldStrength::T3);
+        let spreads = [
+            Spread::Normal {
+                mean: 0.07,
+                sd: -0.01,
+            },
+            Spread::LogNormal {
+                median: 0.0,
+                sigma: 0.1,
+            },
+            Spread::Uniform {
+                min: 0.1,
+                max: 0.03,
+            },
+        ];
+        for spread in spreads {
+            assert!(spread.validate().is_err());
+            let uncertain = UncertainTissue::new(wm.clone()).with(Property::T2, spread);
+            assert!(uncertain.sample(&mut StdRng::seed_from_u64(1)).is_err());
+            assert!(Sampler::new(10, 4).signal(&spin_echo(), &uncertain).is_err());
+        }
+
+        // a valid spread without physical values
+        let negative = UncertainTissue::new(wm).with(
+            Property::T1,
+            Spread::Uniform {
+                min: -2.0,
+                max: -1.0,
+            },
+        );
+        assert!(negative.validate().is_ok());
+        assert!(negative.sample(&mut StdRng::seed_from_u64(1)).is_err());
+        assert!(Sampler::new(10, 4).signal(&spin_echo(), &negative).is_err());
+    }
+}